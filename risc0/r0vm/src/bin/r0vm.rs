@@ -12,18 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{fs, path::PathBuf};
+#[cfg(feature = "gdbstub")]
+use std::net::TcpListener;
+use std::{fs, io::BufReader, path::PathBuf};
 
-use clap::Parser;
-use risc0_zkvm::{Executor, ExecutorEnv};
+use clap::{Parser, Subcommand};
+use risc0_zkvm::{diff_traces, Executor, ExecutorEnv, Session, TraceEvent, TraceRecorder};
 
 /// Runs a RISC-V ELF binary within the RISC Zero ZKVM.
 #[derive(Parser)]
-#[clap(about, version, author)]
+#[clap(about, version, author, subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// The ELF file to run
-    #[clap(long)]
-    elf: PathBuf,
+    #[clap(long, required = true)]
+    elf: Option<PathBuf>,
 
     /// Receipt output file.
     #[clap(long)]
@@ -41,36 +46,107 @@ struct Args {
     #[clap(long, action = clap::ArgAction::Append)]
     env: Vec<String>,
 
+    /// Write a trace of the syscalls made by the guest to this file, as JSON
+    /// lines.
+    #[clap(long)]
+    strace: Option<PathBuf>,
+
+    /// Write a trace of the program counter and the registers and memory
+    /// written by each instruction to this file, for use with `trace-diff`.
+    #[clap(long)]
+    trace: Option<PathBuf>,
+
     /// Write "pprof" protobuf output of the guest's run to this file.
     /// You can use google's pprof (<https://github.com/google/pprof>)
     /// to read it.
     #[cfg(feature = "profiler")]
     #[clap(long)]
     pprof_out: Option<PathBuf>,
+
+    /// Write an SVG flamegraph of the cycles spent in each guest call stack
+    /// to this file.
+    #[cfg(feature = "profiler")]
+    #[clap(long)]
+    flamegraph: Option<PathBuf>,
+
+    /// Write an LCOV report of the guest source lines executed to this file.
+    #[cfg(feature = "coverage")]
+    #[clap(long)]
+    lcov: Option<PathBuf>,
+
+    /// Wait for a GDB connection on this port before running the guest.
+    /// Attach to it with `target remote localhost:<port>`.
+    #[cfg(feature = "gdbstub")]
+    #[clap(long)]
+    gdb: Option<u16>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Report the first instruction at which two traces recorded with
+    /// `--trace` differ.
+    TraceDiff(TraceDiffArgs),
+}
+
+#[derive(clap::Args)]
+struct TraceDiffArgs {
+    /// The first trace file.
+    left: PathBuf,
+
+    /// The second trace file.
+    right: PathBuf,
+
+    /// The ELF file the traces were recorded from, used to find the source
+    /// location of the divergent instructions.
+    #[cfg(feature = "backtrace")]
+    #[clap(long)]
+    elf: Option<PathBuf>,
+
+    /// The ELF file the second trace was recorded from, if it differs from
+    /// `--elf`.
+    #[cfg(feature = "backtrace")]
+    #[clap(long, requires = "elf")]
+    right_elf: Option<PathBuf>,
 }
 
 fn main() {
     env_logger::init();
 
     let args = Args::parse();
-    let elf_contents = fs::read(&args.elf).unwrap();
+    if let Some(Command::TraceDiff(diff_args)) = args.command.as_ref() {
+        trace_diff(diff_args);
+        return;
+    }
+
+    let elf = args.elf.as_ref().unwrap();
+    let elf_contents = fs::read(elf).unwrap();
 
     if args.verbose > 0 {
         eprintln!(
             "Read {} bytes of ELF from {}",
             elf_contents.len(),
-            args.elf.display()
+            elf.display()
         );
     }
 
     #[cfg(feature = "profiler")]
     let mut guest_prof: Option<risc0_zkvm::Profiler> = None;
     #[cfg(feature = "profiler")]
-    if args.pprof_out.is_some() {
-        guest_prof =
-            Some(risc0_zkvm::Profiler::new(args.elf.to_str().unwrap(), &elf_contents).unwrap());
+    if args.pprof_out.is_some() || args.flamegraph.is_some() {
+        guest_prof = Some(risc0_zkvm::Profiler::new(elf.to_str().unwrap(), &elf_contents).unwrap());
     }
 
+    #[cfg(feature = "coverage")]
+    let mut coverage = args
+        .lcov
+        .as_ref()
+        .map(|_| risc0_zkvm::Coverage::new(&elf_contents).unwrap());
+
+    let mut recorder = args
+        .trace
+        .as_ref()
+        .map(|path| TraceRecorder::new(fs::File::create(path).unwrap()));
+
     let session = {
         let mut builder = ExecutorEnv::builder();
 
@@ -85,23 +161,67 @@ fn main() {
             builder.stdin(fs::File::open(input).unwrap());
         }
 
+        if let Some(strace) = args.strace.as_ref() {
+            builder.trace_syscalls(fs::File::create(strace).unwrap());
+        }
+
+        #[cfg(feature = "backtrace")]
+        builder.backtrace(true);
+
+        let mut callbacks: Vec<Box<dyn FnMut(TraceEvent) -> anyhow::Result<()> + Send + '_>> =
+            Vec::new();
+
+        if let Some(ref mut recorder) = recorder {
+            callbacks.push(Box::new(recorder.make_trace_callback()));
+        }
+
         #[cfg(feature = "profiler")]
         if let Some(ref mut profiler) = guest_prof {
-            builder.trace_callback(profiler.make_trace_callback());
+            callbacks.push(Box::new(profiler.make_trace_callback()));
+        }
+
+        #[cfg(feature = "coverage")]
+        if let Some(ref mut coverage) = coverage {
+            callbacks.push(Box::new(coverage.make_trace_callback()));
+        }
+
+        if !callbacks.is_empty() {
+            builder.trace_callback(move |event| {
+                for callback in callbacks.iter_mut() {
+                    callback(event.clone())?;
+                }
+                Ok(())
+            });
         }
 
         let env = builder.build().unwrap();
         let mut exec = Executor::from_elf(env, &elf_contents).unwrap();
-        exec.run().unwrap()
+        run(&mut exec, &args)
     };
 
     // Now that we're done with the prover, we can collect the guest profiling data.
     #[cfg(feature = "profiler")]
     if let Some(ref mut profiler) = guest_prof.as_mut() {
-        profiler.finalize();
-        let report = profiler.encode_to_vec();
-        fs::write(args.pprof_out.as_ref().unwrap(), &report)
-            .expect("Unable to write profiling output");
+        if let Some(pprof_out) = args.pprof_out.as_ref() {
+            profiler.finalize();
+            let report = profiler.encode_to_vec();
+            fs::write(pprof_out, &report).expect("Unable to write profiling output");
+        }
+        if let Some(flamegraph) = args.flamegraph.as_ref() {
+            let mut svg = Vec::new();
+            match profiler.write_flamegraph(&mut svg) {
+                Ok(()) => fs::write(flamegraph, svg).expect("Unable to write flamegraph output"),
+                Err(err) => eprintln!("Skipping flamegraph: {err}"),
+            }
+        }
+    }
+
+    #[cfg(feature = "coverage")]
+    if let Some(coverage) = coverage.as_ref() {
+        let file = fs::File::create(args.lcov.as_ref().unwrap()).unwrap();
+        coverage
+            .write_lcov(file)
+            .expect("Unable to write coverage output");
     }
 
     let receipt = session.prove().unwrap();
@@ -118,3 +238,48 @@ fn main() {
         }
     }
 }
+
+fn trace_diff(args: &TraceDiffArgs) {
+    let left = BufReader::new(fs::File::open(&args.left).unwrap());
+    let right = BufReader::new(fs::File::open(&args.right).unwrap());
+    let Some(divergence) = diff_traces(left, right).unwrap() else {
+        println!("Traces are identical");
+        return;
+    };
+
+    #[cfg(feature = "backtrace")]
+    let divergence = {
+        let mut divergence = divergence;
+        if let Some(elf) = args.elf.as_ref() {
+            let left_elf = fs::read(elf).unwrap();
+            let right_elf = match args.right_elf.as_ref() {
+                Some(right_elf) => fs::read(right_elf).unwrap(),
+                None => left_elf.clone(),
+            };
+            divergence.symbolize(&left_elf, &right_elf).unwrap();
+        }
+        divergence
+    };
+
+    println!("{divergence}");
+    std::process::exit(1);
+}
+
+#[cfg(feature = "gdbstub")]
+fn run(exec: &mut Executor, args: &Args) -> Session {
+    let Some(port) = args.gdb else {
+        return exec.run().unwrap();
+    };
+    let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+    eprintln!("Waiting for GDB to connect on port {port}");
+    let (stream, addr) = listener.accept().unwrap();
+    if args.verbose > 0 {
+        eprintln!("GDB connected from {addr}");
+    }
+    exec.run_with_gdb(stream).unwrap()
+}
+
+#[cfg(not(feature = "gdbstub"))]
+fn run(exec: &mut Executor, _args: &Args) -> Session {
+    exec.run().unwrap()
+}
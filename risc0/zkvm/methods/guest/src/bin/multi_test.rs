@@ -18,19 +18,23 @@
 #![no_std]
 
 extern crate alloc;
-use alloc::vec;
+use alloc::{string::String, vec, vec::Vec};
 use core::arch::asm;
 
 use getrandom::getrandom;
 use risc0_zkp::core::hash::sha::testutil::test_sha_impl;
 use risc0_zkvm::{
-    guest::{env, memory_barrier, sha},
+    guest::{
+        env,
+        fs::{self, File, SeekFrom},
+        memory_barrier, sha,
+    },
     sha::{Digest, Sha256},
 };
 use risc0_zkvm_methods::multi_test::{MultiTestSpec, SYS_MULTI_TEST};
 use risc0_zkvm_platform::{
     fileno, memory,
-    syscall::{bigint, sys_bigint, sys_read, sys_write},
+    syscall::{bigint, seek, sys_bigint, sys_close, sys_read, sys_seek, sys_stat, sys_write},
 };
 
 risc0_zkvm::entry!(main);
@@ -146,6 +150,14 @@ pub fn main() {
             env::pause();
             env::log("after");
         }
+        MultiTestSpec::DoRandomPause => {
+            let mut rand_buf = [0u8; 7];
+            getrandom(rand_buf.as_mut_slice()).expect("random number generation failed");
+            env::commit_slice(&rand_buf);
+            env::pause();
+            getrandom(rand_buf.as_mut_slice()).expect("random number generation failed");
+            env::commit_slice(&rand_buf);
+        }
         MultiTestSpec::CopyToStdout { fd } => {
             // Unaligned buffer size to exercise things a little bit.
             const BUF_SIZE: usize = 9;
@@ -200,5 +212,38 @@ pub fn main() {
             let len = (memory::STACK_TOP - memory::RESERVED_STACK) as usize;
             let _data = black_box(vec![0_u8; len]);
         }
+        MultiTestSpec::NamedInputs => {
+            // Read in the opposite order to the host adding them, and twice.
+            let count: u32 = env::read_named("count");
+            let greeting: String = env::read_named("greeting");
+            assert_eq!(env::read_named::<u32>("count"), count);
+            env::commit(&(greeting, count));
+        }
+        MultiTestSpec::ReadFile { path, offset } => {
+            let contents = File::open(&path).ok().map(|mut file| {
+                assert_eq!(file.seek(SeekFrom::Start(offset)), Ok(offset));
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf);
+                (file.size(), buf)
+            });
+            if contents.is_none() {
+                assert_eq!(fs::read(&path), Err(fs::Error::NotFound));
+            }
+            env::commit(&contents);
+        }
+        MultiTestSpec::OpenFiles { path, count } => {
+            for _ in 0..count {
+                assert!(fs::read(&path).is_ok());
+            }
+            // Files beyond the limit on open files can't be opened.
+            let files: Vec<_> = (0..count).map_while(|_| File::open(&path).ok()).collect();
+            env::commit(&(files.len() as u32));
+        }
+        MultiTestSpec::BadFileDescriptor { fd } => unsafe {
+            sys_close(fd);
+            let pos = sys_seek(fd, 0, seek::SET);
+            let size = sys_stat(fd);
+            env::commit(&(pos, size));
+        },
     }
 }
@@ -15,7 +15,7 @@
 // Definitions for test selection codes used by the "multi_test" test.
 extern crate alloc;
 
-use alloc::vec::Vec;
+use alloc::{string::String, vec::Vec};
 
 use risc0_zkvm::declare_syscall;
 use risc0_zkvm_platform::syscall::bigint;
@@ -57,12 +57,33 @@ pub enum MultiTestSpec {
         modulus: [u32; bigint::WIDTH_WORDS],
     },
     PauseContinue,
+    /// Like DoRandom, but pause before generating more random numbers
+    DoRandomPause,
     BusyLoop {
         /// Busy loop until the guest has run for at least this number of cycles
         cycles: u32,
     },
     LibM,
     Oom,
+    NamedInputs,
+    ReadFile {
+        /// Path to open in the virtual filesystem
+        path: String,
+        /// Position to seek to before reading the rest of the file
+        offset: u32,
+    },
+    /// Open, read and close a file from the virtual filesystem many times
+    OpenFiles {
+        /// Path to open in the virtual filesystem
+        path: String,
+        /// Number of times to open the file
+        count: u32,
+    },
+    /// Close, seek and stat a file descriptor which was never opened
+    BadFileDescriptor {
+        /// File descriptor to use
+        fd: u32,
+    },
 }
 
 declare_syscall!(pub SYS_MULTI_TEST);
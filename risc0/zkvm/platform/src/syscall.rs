@@ -32,6 +32,13 @@ pub mod halt {
     pub const SPLIT: u32 = 2;
 }
 
+/// Origins for sys_seek, as with POSIX lseek.
+pub mod seek {
+    pub const SET: u32 = 0;
+    pub const CUR: u32 = 1;
+    pub const END: u32 = 2;
+}
+
 pub mod reg_abi {
     pub const REG_ZERO: usize = 0; // zero constant
     pub const REG_RA: usize = 1; // return address
@@ -117,13 +124,18 @@ macro_rules! declare_syscall {
 }
 
 pub mod nr {
+    declare_syscall!(pub SYS_CLOSE);
     declare_syscall!(pub SYS_CYCLE_COUNT);
     declare_syscall!(pub SYS_GETENV);
     declare_syscall!(pub SYS_LOG);
+    declare_syscall!(pub SYS_OPEN);
     declare_syscall!(pub SYS_PANIC);
     declare_syscall!(pub SYS_RANDOM);
     declare_syscall!(pub SYS_READ_AVAIL);
+    declare_syscall!(pub SYS_READ_NAMED);
     declare_syscall!(pub SYS_READ);
+    declare_syscall!(pub SYS_SEEK);
+    declare_syscall!(pub SYS_STAT);
     declare_syscall!(pub SYS_WRITE);
 }
 
@@ -467,6 +479,40 @@ pub unsafe extern "C" fn sys_write(fd: u32, write_buf: *const u8, nbytes: usize)
     );
 }
 
+/// Opens the file at the given path in the host's virtual filesystem for
+/// reading with sys_read.  Returns the new file descriptor, or u32::MAX
+/// if the file doesn't exist or too many files are already open.
+#[no_mangle]
+pub unsafe extern "C" fn sys_open(path: *const u8, path_len: usize) -> u32 {
+    let Return(fd, _) = syscall_2(nr::SYS_OPEN, null_mut(), 0, path as u32, path_len as u32);
+    fd
+}
+
+/// Moves the read position of a file opened with sys_open to `offset`
+/// bytes from the given origin, one of the constants in [seek].  Returns
+/// the new position, or u32::MAX if it would be out of range, `whence` is
+/// not a valid origin or `fd` is not an open file.
+#[no_mangle]
+pub unsafe extern "C" fn sys_seek(fd: u32, offset: i32, whence: u32) -> u32 {
+    let Return(pos, _) = syscall_3(nr::SYS_SEEK, null_mut(), 0, fd, offset as u32, whence);
+    pos
+}
+
+/// Returns the size in bytes of a file opened with sys_open, or u32::MAX
+/// if `fd` is not an open file or its size doesn't fit in a u32.
+#[no_mangle]
+pub unsafe extern "C" fn sys_stat(fd: u32) -> u32 {
+    let Return(size, _) = syscall_1(nr::SYS_STAT, null_mut(), 0, fd);
+    size
+}
+
+/// Closes a file opened with sys_open, freeing its file descriptor.  Does
+/// nothing if `fd` is not an open file.
+#[no_mangle]
+pub unsafe extern "C" fn sys_close(fd: u32) {
+    syscall_1(nr::SYS_CLOSE, null_mut(), 0, fd);
+}
+
 /// Retrieves the value of an environment variable, and stores as much
 /// of it as it can it in the memory at [out_words, out_words +
 /// out_nwords).  Returns the length of the value.
@@ -495,6 +541,34 @@ pub unsafe extern "C" fn sys_getenv(
     }
 }
 
+/// Retrieves the serialized input the host provided under the given name,
+/// and stores as much of it as it can in the memory at [out_words,
+/// out_words + out_nwords).  Returns the length of the input in words, or
+/// usize::MAX if there is no input with that name.
+///
+/// Like sys_getenv, this is normally called twice: once to get the length
+/// of the input, and once to fill in allocated memory.
+#[no_mangle]
+pub unsafe extern "C" fn sys_read_named(
+    out_words: *mut u32,
+    out_nwords: usize,
+    name: *const u8,
+    name_len: usize,
+) -> usize {
+    let Return(a0, _) = syscall_2(
+        nr::SYS_READ_NAMED,
+        out_words,
+        out_nwords,
+        name as u32,
+        name_len as u32,
+    );
+    if a0 == u32::MAX {
+        usize::MAX
+    } else {
+        a0 as usize
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn sys_alloc_words(nwords: usize) -> *mut u32 {
     sys_alloc_aligned(WORD_SIZE * nwords, WORD_SIZE) as *mut u32
@@ -0,0 +1,288 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Backtraces of the guest, captured when it panics or faults.
+//!
+//! With the `backtrace` feature, the guest stack is unwound using the DWARF
+//! call frame information (CFI) in the ELF, falling back to following frame
+//! pointers for code without CFI, and each frame is symbolized using its debug
+//! info.
+
+/// A single frame of a [Backtrace].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BacktraceFrame {
+    /// The program counter of the frame; for callers, this is the address of
+    /// the call instruction.
+    pub pc: u32,
+
+    /// The demangled name of the function, if known.
+    pub function: Option<String>,
+
+    /// The source file, if known.
+    pub file: Option<String>,
+
+    /// The line number within the source file, if known.
+    pub line: Option<u32>,
+}
+
+/// A backtrace of the guest stack, innermost frame first.
+///
+/// Functions inlined into another function produce several frames with the
+/// same program counter.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Backtrace {
+    /// The frames of the backtrace.
+    pub frames: Vec<BacktraceFrame>,
+}
+
+impl core::fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for (idx, frame) in self.frames.iter().enumerate() {
+            write!(f, "\n{idx:4}: 0x{:08x}", frame.pc)?;
+            if let Some(function) = &frame.function {
+                write!(f, " - {function}")?;
+            }
+            if let Some(file) = &frame.file {
+                write!(f, "\n          at {file}")?;
+                if let Some(line) = frame.line {
+                    write!(f, ":{line}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "backtrace")]
+pub(crate) use self::unwind::{load_context, Symbols};
+
+#[cfg(feature = "backtrace")]
+mod unwind {
+    use std::{borrow::Cow, sync::Arc};
+
+    use addr2line::{
+        fallible_iterator::FallibleIterator,
+        object::{read::File, Object, ObjectSection},
+        Context,
+    };
+    use anyhow::Result;
+    use gimli::{
+        BaseAddresses, CfaRule, DebugFrame, EhFrame, EndianArcSlice, RegisterRule, RiscV,
+        RunTimeEndian, UnwindContext, UnwindSection,
+    };
+    use risc0_zkvm_platform::{syscall::reg_abi::REG_MAX, WORD_SIZE};
+
+    use super::{Backtrace, BacktraceFrame};
+
+    type Reader = EndianArcSlice<RunTimeEndian>;
+
+    /// The maximum number of frames to unwind, in case the stack is corrupt.
+    const MAX_FRAMES: usize = 256;
+
+    // Like [Context::new], but backed by reference counted slices which can be
+    // sent to other threads along with the executor.
+    pub(crate) fn load_context(file: &File) -> Result<Context<Reader>> {
+        let endian = endian(file);
+        let dwarf = gimli::Dwarf::load(|id| -> Result<_, gimli::Error> {
+            Ok(load_section(file, id.name(), endian))
+        })?;
+        Ok(Context::from_dwarf(dwarf)?)
+    }
+
+    fn endian(file: &File) -> RunTimeEndian {
+        if file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        }
+    }
+
+    fn load_section(file: &File, name: &str, endian: RunTimeEndian) -> Reader {
+        let data = file
+            .section_by_name(name)
+            .and_then(|section| section.uncompressed_data().ok())
+            .unwrap_or(Cow::Borrowed(&[]));
+        EndianArcSlice::new(Arc::from(&*data), endian)
+    }
+
+    /// The unwind tables and debug info of a guest ELF.
+    pub(crate) struct Symbols {
+        ctx: Context<Reader>,
+        eh_frame: EhFrame<Reader>,
+        debug_frame: DebugFrame<Reader>,
+        bases: BaseAddresses,
+    }
+
+    impl Symbols {
+        pub fn new(elf: &[u8]) -> Result<Self> {
+            let file = File::parse(elf)?;
+            let endian = endian(&file);
+            let mut eh_frame = EhFrame::from(load_section(&file, ".eh_frame", endian));
+            eh_frame.set_address_size(WORD_SIZE as u8);
+            let mut debug_frame = DebugFrame::from(load_section(&file, ".debug_frame", endian));
+            debug_frame.set_address_size(WORD_SIZE as u8);
+            let mut bases = BaseAddresses::default();
+            if let Some(section) = file.section_by_name(".eh_frame") {
+                bases = bases.set_eh_frame(section.address());
+            }
+            if let Some(section) = file.section_by_name(".text") {
+                bases = bases.set_text(section.address());
+            }
+            Ok(Self {
+                ctx: load_context(&file)?,
+                eh_frame,
+                debug_frame,
+                bases,
+            })
+        }
+
+        /// Unwind the guest stack starting from `pc` with the given register
+        /// values, reading saved registers from memory with `read_u32`.
+        pub fn backtrace(
+            &self,
+            mut pc: u32,
+            mut regs: [u32; REG_MAX],
+            read_u32: impl Fn(u32) -> Option<u32>,
+        ) -> Backtrace {
+            let mut backtrace = Backtrace::default();
+            for _ in 0..MAX_FRAMES {
+                if !self.symbolize(pc, &mut backtrace) {
+                    break;
+                }
+                let sp = regs[RiscV::SP.0 as usize];
+                let ra = self
+                    .unwind(&self.eh_frame, pc, &mut regs, &read_u32)
+                    .or_else(|| self.unwind(&self.debug_frame, pc, &mut regs, &read_u32))
+                    .or_else(|| unwind_frame_pointer(&mut regs, &read_u32));
+                match ra {
+                    // Stop once there's no caller, or if the stack pointer
+                    // moved the wrong way.
+                    Some(ra) if ra >= WORD_SIZE as u32 && regs[RiscV::SP.0 as usize] >= sp => {
+                        pc = ra - WORD_SIZE as u32;
+                    }
+                    _ => break,
+                }
+            }
+            backtrace
+        }
+
+        /// The frames at `pc` alone, with inlined functions resolved.
+        pub fn locate(&self, pc: u32) -> Backtrace {
+            let mut backtrace = Backtrace::default();
+            self.symbolize(pc, &mut backtrace);
+            backtrace
+        }
+
+        // Append the frames at `pc` to the backtrace, returning false if `pc`
+        // isn't within any known function.
+        fn symbolize(&self, pc: u32, backtrace: &mut Backtrace) -> bool {
+            let frames = match self.ctx.find_frames(pc as u64).skip_all_loads() {
+                Ok(frames) => frames,
+                Err(_) => return false,
+            };
+            let frames: Vec<_> = frames
+                .map(|frame| {
+                    Ok(BacktraceFrame {
+                        pc,
+                        function: frame
+                            .function
+                            .as_ref()
+                            .and_then(|function| function.demangle().ok())
+                            .map(|name| name.to_string()),
+                        file: frame
+                            .location
+                            .as_ref()
+                            .and_then(|location| location.file)
+                            .map(str::to_string),
+                        line: frame.location.as_ref().and_then(|location| location.line),
+                    })
+                })
+                .collect::<Vec<_>>()
+                .unwrap_or_default();
+            if frames.is_empty() {
+                // Without debug info, report the bare address of the first
+                // frame only.
+                if backtrace.frames.is_empty() {
+                    backtrace.frames.push(BacktraceFrame {
+                        pc,
+                        function: None,
+                        file: None,
+                        line: None,
+                    });
+                }
+                return false;
+            }
+            backtrace.frames.extend(frames);
+            true
+        }
+
+        // Restore the registers of the caller of the function at `pc` using
+        // its CFI, returning the return address.
+        fn unwind<S: UnwindSection<Reader>>(
+            &self,
+            section: &S,
+            pc: u32,
+            regs: &mut [u32; REG_MAX],
+            read_u32: &impl Fn(u32) -> Option<u32>,
+        ) -> Option<u32> {
+            let mut ctx = UnwindContext::new();
+            let row = section
+                .unwind_info_for_address(&self.bases, &mut ctx, pc as u64, S::cie_from_offset)
+                .ok()?;
+            let cfa = match row.cfa() {
+                CfaRule::RegisterAndOffset { register, offset } => {
+                    (*regs.get(register.0 as usize)? as i64 + offset) as u32
+                }
+                CfaRule::Expression(_) => return None,
+            };
+
+            let mut caller = *regs;
+            for (register, rule) in row.registers() {
+                let idx = register.0 as usize;
+                if idx >= REG_MAX {
+                    continue;
+                }
+                caller[idx] = match rule {
+                    RegisterRule::Offset(offset) => read_u32((cfa as i64 + offset) as u32)?,
+                    RegisterRule::ValOffset(offset) => (cfa as i64 + offset) as u32,
+                    RegisterRule::Register(other) => *regs.get(other.0 as usize)?,
+                    RegisterRule::SameValue => regs[idx],
+                    _ => return None,
+                };
+            }
+            // A return address without a rule hasn't been saved, as in a leaf
+            // function, so it is still in the register.
+            caller[RiscV::SP.0 as usize] = cfa;
+            let ra = caller[RiscV::RA.0 as usize];
+            *regs = caller;
+            Some(ra)
+        }
+    }
+
+    // Restore the registers of the caller using the frame pointer, which
+    // points just above the saved return address and frame pointer.
+    fn unwind_frame_pointer(
+        regs: &mut [u32; REG_MAX],
+        read_u32: &impl Fn(u32) -> Option<u32>,
+    ) -> Option<u32> {
+        let fp = regs[RiscV::S0.0 as usize];
+        if fp < 2 * WORD_SIZE as u32 || fp % WORD_SIZE as u32 != 0 {
+            return None;
+        }
+        let ra = read_u32(fp - WORD_SIZE as u32)?;
+        regs[RiscV::S0.0 as usize] = read_u32(fp - 2 * WORD_SIZE as u32)?;
+        regs[RiscV::SP.0 as usize] = fp;
+        Some(ra)
+    }
+}
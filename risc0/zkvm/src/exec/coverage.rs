@@ -0,0 +1,108 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Source-level code coverage of the guest.
+//!
+//! This counts the number of times each instruction is executed, and maps
+//! those counts back to source lines using the DWARF line tables of the guest
+//! ELF. Every line with instructions is reported, so lines which were never
+//! executed show up with a count of zero.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+};
+
+use addr2line::{object::read::File, Context};
+use anyhow::Result;
+use gimli::{EndianArcSlice, RunTimeEndian};
+use risc0_zkvm_platform::WORD_SIZE;
+
+use super::{backtrace::load_context, TraceEvent};
+
+/// Collects the code coverage of a guest.
+pub struct Coverage {
+    // Number of times each program counter was executed
+    counts: HashMap<u32, u64>,
+
+    ctx: Context<EndianArcSlice<RunTimeEndian>>,
+}
+
+impl Coverage {
+    /// Return a new coverage collector for the given RISCV ELF.
+    pub fn new(elf_data: &[u8]) -> Result<Self> {
+        let file = File::parse(elf_data)?;
+        Ok(Self {
+            counts: HashMap::new(),
+            ctx: load_context(&file)?,
+        })
+    }
+
+    /// Returns a callback to populate this collector, suitable for passing
+    /// to [super::ExecutorEnvBuilder::trace_callback].
+    pub fn make_trace_callback<'a>(
+        &'a mut self,
+    ) -> impl FnMut(TraceEvent) -> anyhow::Result<()> + Send + 'a {
+        |event| {
+            if let TraceEvent::InstructionStart { pc, .. } = event {
+                *self.counts.entry(pc).or_insert(0) += 1;
+            }
+            Ok(())
+        }
+    }
+
+    /// Returns the execution count of each source line, keyed by file name
+    /// and then line number.
+    ///
+    /// The count of a line is the largest count of any of its instructions.
+    pub fn lines(&self) -> Result<BTreeMap<String, BTreeMap<u32, u64>>> {
+        let mut files: BTreeMap<String, BTreeMap<u32, u64>> = BTreeMap::new();
+        for (addr, len, loc) in self.ctx.find_location_range(0, u32::MAX as u64 + 1)? {
+            let (Some(file), Some(line)) = (loc.file, loc.line) else {
+                continue;
+            };
+            let count = (addr..addr + len)
+                .step_by(WORD_SIZE)
+                .filter_map(|pc| self.counts.get(&(pc as u32)))
+                .copied()
+                .max()
+                .unwrap_or(0);
+            let entry = files
+                .entry(file.to_string())
+                .or_default()
+                .entry(line)
+                .or_insert(0);
+            *entry = (*entry).max(count);
+        }
+        Ok(files)
+    }
+
+    /// Write the coverage in the LCOV tracefile format, as read by `genhtml`
+    /// and most coverage services.
+    pub fn write_lcov(&self, mut writer: impl Write) -> Result<()> {
+        writeln!(writer, "TN:")?;
+        for (file, lines) in self.lines()? {
+            writeln!(writer, "SF:{file}")?;
+            for (line, count) in lines.iter() {
+                writeln!(writer, "DA:{line},{count}")?;
+            }
+            writeln!(writer, "LF:{}", lines.len())?;
+            let hit = lines.values().filter(|&&count| count > 0).count();
+            writeln!(writer, "LH:{hit}")?;
+            writeln!(writer, "end_of_record")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -15,10 +15,13 @@
 //! This module defines the [ExecutorEnv] and [ExecutorEnvBuilder].
 
 use std::{
-    cell::RefCell,
     collections::HashMap,
     io::{BufRead, BufReader, Cursor, Read, Write},
-    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
 };
 
 use anyhow::Result;
@@ -26,39 +29,204 @@ use bytemuck::Pod;
 use risc0_zkvm_platform::{
     fileno,
     syscall::{
-        nr::{SYS_GETENV, SYS_READ, SYS_READ_AVAIL, SYS_WRITE},
+        nr::{
+            SYS_CLOSE, SYS_GETENV, SYS_OPEN, SYS_RANDOM, SYS_READ, SYS_READ_AVAIL, SYS_READ_NAMED,
+            SYS_SEEK, SYS_STAT, SYS_WRITE,
+        },
         SyscallName,
     },
 };
+use serde::Serialize;
 
 use super::{
-    io::{slice_io_from_fn, syscalls, PosixIo, SliceIo, Syscall, SyscallTable},
+    io::{
+        slice_io_from_fn,
+        syscalls::{self, RandomState},
+        PosixIo, SliceIo, Syscall, SyscallTable,
+    },
+    replay::{SyscallRecorder, SyscallReplayer},
+    strace::SyscallTracer,
+    vfs::MountSource,
     TraceEvent,
 };
+use crate::{
+    serde::to_vec,
+    session::{OutputStream, RandomSource},
+};
 
 /// The default segment limit specified in powers of 2 cycles. Choose this value
 /// to try and fit with 8GB of RAM.
 const DEFAULT_SEGMENT_LIMIT_PO2: usize = 20; // 1M cycles
 
+/// The kind of handlers held by an [ExecutorEnv], which determines whether the
+/// environment, and an [super::Executor] constructed from it, can be sent to
+/// another thread.
+///
+/// This is implemented by [LocalHandlers] and [SendHandlers] only.
+pub trait Handlers<'a>: sealed::Sealed + Clone {
+    /// The type of syscall handlers.
+    type Syscall: Syscall + ?Sized + 'a;
+
+    /// The type of readers, for file descriptors and syscall logs.
+    type Reader: BufRead + ?Sized + 'a;
+
+    /// The type of writers, for file descriptors, syscall logs and traces.
+    type Writer: Write + ?Sized + 'a;
+
+    /// The type of trace callbacks.
+    type TraceCallback: FnMut(TraceEvent) -> Result<()> + ?Sized + 'a;
+
+    #[doc(hidden)]
+    fn syscall<T: Syscall + Send + 'a>(handler: Arc<Mutex<T>>) -> Arc<Mutex<Self::Syscall>>;
+
+    #[doc(hidden)]
+    fn reader<T: BufRead + Send + 'a>(reader: Arc<Mutex<T>>) -> Arc<Mutex<Self::Reader>>;
+
+    #[doc(hidden)]
+    fn writer<T: Write + Send + 'a>(writer: Arc<Mutex<T>>) -> Arc<Mutex<Self::Writer>>;
+
+    #[doc(hidden)]
+    fn posix_io(io: Arc<Mutex<PosixIo<'a, Self>>>) -> Arc<Mutex<Self::Syscall>>;
+}
+
+/// [Handlers] which need not be [Send], used by [ExecutorEnv::builder].
+#[derive(Clone, Copy, Debug)]
+pub enum LocalHandlers {}
+
+/// [Handlers] which must be [Send], used by [ExecutorEnv::send_builder].
+#[derive(Clone, Copy, Debug)]
+pub enum SendHandlers {}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for super::LocalHandlers {}
+    impl Sealed for super::SendHandlers {}
+}
+
+impl<'a> Handlers<'a> for LocalHandlers {
+    type Syscall = dyn Syscall + 'a;
+    type Reader = dyn BufRead + 'a;
+    type Writer = dyn Write + 'a;
+    type TraceCallback = dyn FnMut(TraceEvent) -> Result<()> + 'a;
+
+    fn syscall<T: Syscall + Send + 'a>(handler: Arc<Mutex<T>>) -> Arc<Mutex<Self::Syscall>> {
+        handler
+    }
+
+    fn reader<T: BufRead + Send + 'a>(reader: Arc<Mutex<T>>) -> Arc<Mutex<Self::Reader>> {
+        reader
+    }
+
+    fn writer<T: Write + Send + 'a>(writer: Arc<Mutex<T>>) -> Arc<Mutex<Self::Writer>> {
+        writer
+    }
+
+    fn posix_io(io: Arc<Mutex<PosixIo<'a, Self>>>) -> Arc<Mutex<Self::Syscall>> {
+        io
+    }
+}
+
+impl<'a> Handlers<'a> for SendHandlers {
+    type Syscall = dyn Syscall + Send + 'a;
+    type Reader = dyn BufRead + Send + 'a;
+    type Writer = dyn Write + Send + 'a;
+    type TraceCallback = dyn FnMut(TraceEvent) -> Result<()> + Send + 'a;
+
+    fn syscall<T: Syscall + Send + 'a>(handler: Arc<Mutex<T>>) -> Arc<Mutex<Self::Syscall>> {
+        handler
+    }
+
+    fn reader<T: BufRead + Send + 'a>(reader: Arc<Mutex<T>>) -> Arc<Mutex<Self::Reader>> {
+        reader
+    }
+
+    fn writer<T: Write + Send + 'a>(writer: Arc<Mutex<T>>) -> Arc<Mutex<Self::Writer>> {
+        writer
+    }
+
+    fn posix_io(io: Arc<Mutex<PosixIo<'a, Self>>>) -> Arc<Mutex<Self::Syscall>> {
+        io
+    }
+}
+
 /// A builder pattern used to construct an [ExecutorEnv].
 #[derive(Clone)]
-pub struct ExecutorEnvBuilder<'a> {
-    inner: ExecutorEnv<'a>,
+pub struct ExecutorEnvBuilder<'a, H: Handlers<'a> = LocalHandlers> {
+    inner: ExecutorEnv<'a, H>,
+    // The first named input which could not be serialized, reported by build.
+    input_error: Option<(String, crate::serde::Error)>,
 }
 
 /// The [super::Executor] is configured from this object.
 ///
 /// The executor environment holds configuration details that inform how the
 /// guest environment is set up prior to guest program execution.
-#[derive(Clone)]
-pub struct ExecutorEnv<'a> {
+///
+/// An environment built with [ExecutorEnv::send_builder] only holds handlers
+/// which are [Send], so it, and an [super::Executor] constructed from it, can
+/// be sent to another thread. One built with [ExecutorEnv::builder] can hold
+/// any handler, and can't be sent:
+/// ```compile_fail
+/// use risc0_zkvm::ExecutorEnv;
+///
+/// let env = ExecutorEnv::builder().build().unwrap();
+/// std::thread::spawn(move || drop(env));
+/// ```
+pub struct ExecutorEnv<'a, H: Handlers<'a> = LocalHandlers> {
     env_vars: HashMap<String, String>,
     pub(crate) segment_limit_po2: usize,
+    pub(crate) segment_limit_auto: bool,
     session_limit: Option<usize>,
-    syscalls: SyscallTable<'a>,
-    pub(crate) io: Rc<RefCell<PosixIo<'a>>>,
+    pub(crate) time_limit: Option<Duration>,
+    pub(crate) cancellation_token: Option<CancellationToken>,
+    syscalls: SyscallTable<'a, H>,
+    pub(crate) io: Arc<Mutex<PosixIo<'a, H>>>,
     input: Vec<u8>,
-    pub(crate) trace_callback: Option<Rc<RefCell<dyn FnMut(TraceEvent) -> Result<()> + 'a>>>,
+    named_inputs: HashMap<String, Vec<u32>>,
+    mounts: Vec<(String, MountSource)>,
+    pub(crate) trace_callback: Option<Arc<Mutex<H::TraceCallback>>>,
+    pub(crate) syscall_recorder: Option<Arc<Mutex<SyscallRecorder<'a, H>>>>,
+    pub(crate) syscall_replayer: Option<Arc<Mutex<SyscallReplayer<'a, H>>>>,
+    pub(crate) syscall_tracer: Option<Arc<Mutex<SyscallTracer<'a, H>>>>,
+    // The source of the numbers returned by the SYS_RANDOM handler.
+    pub(crate) random_source: RandomSource,
+    // The SYS_RANDOM handler when it is seeded, whose state is saved in
+    // checkpoints.
+    seeded_random: Option<Arc<Mutex<syscalls::Random>>>,
+    pub(crate) capture_output: bool,
+    output_levels: HashMap<OutputStream, log::Level>,
+    #[cfg(feature = "backtrace")]
+    pub(crate) backtrace: bool,
+}
+
+// Not derived, as that would require the handlers themselves to be Clone.
+impl<'a, H: Handlers<'a>> Clone for ExecutorEnv<'a, H> {
+    fn clone(&self) -> Self {
+        Self {
+            env_vars: self.env_vars.clone(),
+            segment_limit_po2: self.segment_limit_po2,
+            segment_limit_auto: self.segment_limit_auto,
+            session_limit: self.session_limit,
+            time_limit: self.time_limit,
+            cancellation_token: self.cancellation_token.clone(),
+            syscalls: self.syscalls.clone(),
+            io: self.io.clone(),
+            input: self.input.clone(),
+            named_inputs: self.named_inputs.clone(),
+            mounts: self.mounts.clone(),
+            trace_callback: self.trace_callback.clone(),
+            syscall_recorder: self.syscall_recorder.clone(),
+            syscall_replayer: self.syscall_replayer.clone(),
+            syscall_tracer: self.syscall_tracer.clone(),
+            random_source: self.random_source,
+            seeded_random: self.seeded_random.clone(),
+            capture_output: self.capture_output,
+            output_levels: self.output_levels.clone(),
+            #[cfg(feature = "backtrace")]
+            backtrace: self.backtrace,
+        }
+    }
 }
 
 impl<'a> ExecutorEnv<'a> {
@@ -76,7 +244,28 @@ impl<'a> ExecutorEnv<'a> {
     pub fn builder() -> ExecutorEnvBuilder<'a> {
         ExecutorEnvBuilder::default()
     }
+}
+
+impl<'a> ExecutorEnv<'a, SendHandlers> {
+    /// Construct an [ExecutorEnvBuilder] which only accepts handlers that are
+    /// [Send], so that the [ExecutorEnv] it builds can be sent to another
+    /// thread.
+    /// # Example
+    /// ```
+    /// use risc0_zkvm::ExecutorEnv;
+    ///
+    /// let env = ExecutorEnv::send_builder()
+    ///     .stdout_send(std::io::sink())
+    ///     .build()
+    ///     .unwrap();
+    /// std::thread::spawn(move || drop(env));
+    /// ```
+    pub fn send_builder() -> ExecutorEnvBuilder<'a, SendHandlers> {
+        ExecutorEnvBuilder::new()
+    }
+}
 
+impl<'a, H: Handlers<'a>> ExecutorEnv<'a, H> {
     pub(crate) fn get_segment_limit(&self) -> usize {
         1 << self.segment_limit_po2
     }
@@ -85,9 +274,32 @@ impl<'a> ExecutorEnv<'a> {
         self.session_limit
     }
 
-    pub(crate) fn get_syscall(&self, name: &str) -> Option<&Rc<RefCell<(dyn Syscall + 'a)>>> {
+    /// The level at which captured output on `stream` is logged, if at all.
+    pub(crate) fn get_output_level(&self, stream: OutputStream) -> Option<log::Level> {
+        self.output_levels.get(&stream).copied()
+    }
+
+    pub(crate) fn get_syscall(&self, name: &str) -> Option<&Arc<Mutex<H::Syscall>>> {
         self.syscalls.inner.get(name)
     }
+
+    /// The state of the seeded SYS_RANDOM handler, if there is one.
+    pub(crate) fn get_random_state(&self) -> Option<RandomState> {
+        self.seeded_random.as_ref()?.lock().unwrap().state()
+    }
+
+    /// Replace the SYS_RANDOM handler with one seeded from `state`, unless a
+    /// custom handler was given.
+    pub(crate) fn restore_random(&mut self, state: &RandomState) {
+        if self.random_source == RandomSource::Custom {
+            return;
+        }
+        let random = Arc::new(Mutex::new(syscalls::Random::from_state(state)));
+        self.syscalls
+            .with_syscall(SYS_RANDOM, H::syscall(random.clone()));
+        self.random_source = RandomSource::Seeded;
+        self.seeded_random = Some(random);
+    }
 }
 
 impl<'a> Default for ExecutorEnv<'a> {
@@ -98,25 +310,96 @@ impl<'a> Default for ExecutorEnv<'a> {
 
 impl<'a> Default for ExecutorEnvBuilder<'a> {
     fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, H: Handlers<'a>> ExecutorEnvBuilder<'a, H> {
+    fn new() -> Self {
         Self {
             inner: ExecutorEnv {
                 env_vars: Default::default(),
                 segment_limit_po2: DEFAULT_SEGMENT_LIMIT_PO2,
+                segment_limit_auto: false,
                 session_limit: None,
+                time_limit: None,
+                cancellation_token: None,
                 syscalls: Default::default(),
                 io: Default::default(),
                 input: Default::default(),
+                named_inputs: Default::default(),
+                mounts: Default::default(),
                 trace_callback: Default::default(),
+                syscall_recorder: Default::default(),
+                syscall_replayer: Default::default(),
+                syscall_tracer: Default::default(),
+                random_source: syscalls::Random::default().source(),
+                seeded_random: None,
+                capture_output: false,
+                output_levels: HashMap::from([
+                    (OutputStream::Log, log::Level::Info),
+                    (OutputStream::Stdout, log::Level::Info),
+                    (OutputStream::Stderr, log::Level::Warn),
+                ]),
+                #[cfg(feature = "backtrace")]
+                backtrace: false,
             },
+            input_error: None,
         }
     }
 }
 
+/// A handle used to stop a running [super::Executor] from another thread.
+///
+/// Clones of a token share the same state, so cancelling any one of them
+/// cancels them all.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Construct a new [CancellationToken] which has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request that executors using this token stop running.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if [CancellationToken::cancel] has been called since the
+    /// token was created or last reset.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clear a previous cancellation, so that a cancelled executor can be
+    /// resumed.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
 /// [ExecutorEnvBuilder] errors.
 #[derive(Debug)]
 pub enum ExecutorEnvBuilderErr {
     /// Segment limit PO2 falls outside supported range.
     SegmentLimitPo2OutOfBounds { given: usize },
+
+    /// A path passed to [ExecutorEnvBuilder::mount] could not be mounted,
+    /// e.g. because it contains `..`.
+    InvalidMountPath {
+        path: String,
+        /// Why the path could not be mounted.
+        source: anyhow::Error,
+    },
+
+    /// A value passed to [ExecutorEnvBuilder::input] could not be serialized.
+    InvalidInput {
+        name: String,
+        /// Why the value could not be serialized.
+        source: crate::serde::Error,
+    },
 }
 
 impl core::fmt::Display for ExecutorEnvBuilderErr {
@@ -125,11 +408,17 @@ impl core::fmt::Display for ExecutorEnvBuilderErr {
             ExecutorEnvBuilderErr::SegmentLimitPo2OutOfBounds { given } => {
                 write!(f, "Invalid segment_limit_po2: {given}",)
             }
+            ExecutorEnvBuilderErr::InvalidMountPath { path, source } => {
+                write!(f, "Unable to mount {path}: {source}")
+            }
+            ExecutorEnvBuilderErr::InvalidInput { name, source } => {
+                write!(f, "Unable to serialize input {name}: {source}")
+            }
         }
     }
 }
 
-impl<'a> ExecutorEnvBuilder<'a> {
+impl<'a, H: Handlers<'a>> ExecutorEnvBuilder<'a, H> {
     /// Finalize this builder to construct an [ExecutorEnv].
     /// # Example
     /// ```
@@ -139,7 +428,7 @@ impl<'a> ExecutorEnvBuilder<'a> {
     ///
     /// let env = ExecutorEnv::builder().build().unwrap();
     /// ```
-    pub fn build(&mut self) -> Result<ExecutorEnv<'a>, ExecutorEnvBuilderErr> {
+    pub fn build(&mut self) -> Result<ExecutorEnv<'a, H>, ExecutorEnvBuilderErr> {
         // Enforce segment_limit_po2 bounds
         if self.inner.segment_limit_po2 < risc0_zkp::MIN_CYCLES_PO2
             || self.inner.segment_limit_po2 > risc0_zkp::MAX_CYCLES_PO2
@@ -149,23 +438,46 @@ impl<'a> ExecutorEnvBuilder<'a> {
             });
         }
 
+        if let Some((name, source)) = self.input_error.clone() {
+            return Err(ExecutorEnvBuilderErr::InvalidInput { name, source });
+        }
+
         // Construct the executor environment
         let mut result = self.clone();
         let getenv = syscalls::Getenv(self.inner.env_vars.clone());
+        let read_named = syscalls::ReadNamed(self.inner.named_inputs.clone());
         if !self.inner.input.is_empty() {
-            let reader = Cursor::new(self.inner.input.clone());
+            let reader = H::reader(Arc::new(Mutex::new(Cursor::new(self.inner.input.clone()))));
             result
                 .inner
                 .io
-                .borrow_mut()
+                .lock()
+                .unwrap()
                 .with_read_fd(fileno::STDIN, reader);
         }
-        let io = result.inner.io.clone();
+        for (path, source) in self.inner.mounts.iter() {
+            result
+                .inner
+                .io
+                .lock()
+                .unwrap()
+                .mount(path, source.clone())
+                .map_err(|source| ExecutorEnvBuilderErr::InvalidMountPath {
+                    path: path.clone(),
+                    source,
+                })?;
+        }
+        let io = H::posix_io(result.inner.io.clone());
         result
-            .syscall(SYS_GETENV, getenv)
-            .syscall(SYS_READ, io.clone())
-            .syscall(SYS_READ_AVAIL, io.clone())
-            .syscall(SYS_WRITE, io);
+            .add_syscall(SYS_GETENV, H::syscall(Arc::new(Mutex::new(getenv))))
+            .add_syscall(SYS_READ_NAMED, H::syscall(Arc::new(Mutex::new(read_named))))
+            .add_syscall(SYS_READ, io.clone())
+            .add_syscall(SYS_READ_AVAIL, io.clone())
+            .add_syscall(SYS_WRITE, io.clone())
+            .add_syscall(SYS_OPEN, io.clone())
+            .add_syscall(SYS_SEEK, io.clone())
+            .add_syscall(SYS_STAT, io.clone())
+            .add_syscall(SYS_CLOSE, io);
         Ok(result.inner.clone())
     }
 
@@ -175,6 +487,31 @@ impl<'a> ExecutorEnvBuilder<'a> {
     /// [risc0_zkp::MAX_CYCLES_PO2] (inclusive).
     pub fn segment_limit_po2(&mut self, limit: usize) -> &mut Self {
         self.inner.segment_limit_po2 = limit;
+        self.inner.segment_limit_auto = false;
+        self
+    }
+
+    /// Let the executor choose the size of each segment to minimize the total
+    /// cost of proving, using segments of at most `max_po2` cycles in powers
+    /// of 2, or [risc0_zkp::MAX_CYCLES_PO2] if `None`.
+    ///
+    /// Segments are split at the maximum size, which spreads the fixed cost
+    /// of each segment over as many cycles as possible. When the guest exits,
+    /// the final segment is split again, if proving it as smaller segments
+    /// costs less than padding it to a power of 2. Set `max_po2` to the
+    /// largest segment that fits in the memory available to the prover.
+    /// # Example
+    /// ```
+    /// use risc0_zkvm::ExecutorEnv;
+    ///
+    /// let env = ExecutorEnv::builder()
+    ///     .segment_limit_auto(Some(20)) // at most 1M cycles per segment
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn segment_limit_auto(&mut self, max_po2: Option<usize>) -> &mut Self {
+        self.inner.segment_limit_po2 = max_po2.unwrap_or(risc0_zkp::MAX_CYCLES_PO2);
+        self.inner.segment_limit_auto = true;
         self
     }
 
@@ -197,6 +534,38 @@ impl<'a> ExecutorEnvBuilder<'a> {
         self
     }
 
+    /// Set a limit on the wall-clock time spent in each call to
+    /// [super::Executor::run].
+    ///
+    /// When the limit is reached, the run stops with
+    /// [super::ExecutorError::TimeLimit] and can be resumed by calling
+    /// [super::Executor::run] again.
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use risc0_zkvm::ExecutorEnv;
+    ///
+    /// let env = ExecutorEnv::builder()
+    ///     .time_limit(Some(Duration::from_secs(10)))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn time_limit(&mut self, limit: Option<Duration>) -> &mut Self {
+        self.inner.time_limit = limit;
+        self
+    }
+
+    /// Stop execution with [super::ExecutorError::Cancelled] once `token` is
+    /// cancelled.
+    ///
+    /// The run can be resumed by resetting the token and calling
+    /// [super::Executor::run] again.
+    pub fn cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.inner.cancellation_token = Some(token);
+        self
+    }
+
     /// Add environment variables to the guest environment.
     /// # Example
     /// ```
@@ -262,12 +631,161 @@ impl<'a> ExecutorEnvBuilder<'a> {
         self
     }
 
-    /// Add a handler for a raw syscall implementation.
-    pub fn syscall(&mut self, syscall: SyscallName, handler: impl Syscall + 'a) -> &mut Self {
+    /// Add an input which the guest can read by name with
+    /// `risc0_zkvm::guest::env::read_named`.
+    ///
+    /// Unlike [ExecutorEnvBuilder::add_input], named inputs can be read in
+    /// any order, and any number of times. Adding an input with the same
+    /// name again replaces it. If the value can't be serialized,
+    /// [ExecutorEnvBuilder::build] returns an error.
+    ///
+    /// # Example
+    /// ```
+    /// use risc0_zkvm::ExecutorEnv;
+    ///
+    /// let env = ExecutorEnv::builder()
+    ///     .input("a", &400u64)
+    ///     .input("b", &200u64)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn input<T: Serialize>(&mut self, name: &str, value: &T) -> &mut Self {
+        match to_vec(value) {
+            Ok(words) => {
+                self.inner.named_inputs.insert(name.to_string(), words);
+            }
+            Err(err) => {
+                self.input_error.get_or_insert((name.to_string(), err));
+            }
+        }
+        self
+    }
+
+    /// Mount a host directory, host file or buffer at `path` in the guest's
+    /// read-only virtual filesystem.
+    ///
+    /// The guest can then open files below `path` using
+    /// `risc0_zkvm::guest::fs::File`. Paths are `/` separated, and where
+    /// mounts are nested the innermost one is used.
+    ///
+    /// # Example
+    /// ```
+    /// use std::path::Path;
+    ///
+    /// use risc0_zkvm::ExecutorEnv;
+    ///
+    /// let env = ExecutorEnv::builder()
+    ///     .mount("/data", Path::new("testdata"))
+    ///     .mount("/etc/config.json", b"{}".as_slice())
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn mount(&mut self, path: &str, source: impl Into<MountSource>) -> &mut Self {
+        self.inner.mounts.push((path.to_string(), source.into()));
+        self
+    }
+
+    /// Seed the random numbers returned to the guest, e.g. by `env::random` or
+    /// `getrandom`, so that executions which use them are reproducible.
+    ///
+    /// The numbers are generated by a ChaCha20 CSPRNG. Each [crate::Segment]
+    /// records the source of the randomness it consumed in
+    /// [crate::Segment::random], so that seeded executions can be told apart.
+    /// A seeded guest is predictable, so this must not be used in production.
+    pub fn rng_seed(&mut self, seed: u64) -> &mut Self {
+        let random = syscalls::Random::seeded(seed);
+        let source = random.source();
+        let random = Arc::new(Mutex::new(random));
+        self.add_syscall(SYS_RANDOM, H::syscall(random.clone()));
+        self.inner.random_source = source;
+        self.inner.seeded_random = Some(random);
+        self
+    }
+
+    /// Capture the output of the guest into [crate::Session::output], rather
+    /// than writing it to the console of the host.
+    ///
+    /// This captures messages from `env::log` and writes to the guest's
+    /// standard output and standard error, tagging each with the cycle and
+    /// segment in which it was written. Captured output is also logged with
+    /// the `log` crate under the `risc0_zkvm::guest` target, at the levels set
+    /// with [ExecutorEnvBuilder::output_level].
+    pub fn capture_output(&mut self, enable: bool) -> &mut Self {
+        self.inner.capture_output = enable;
+        self
+    }
+
+    /// Set the level at which captured output on `stream` is logged, or
+    /// `None` to not log it.
+    ///
+    /// By default, logs and standard output are logged at [log::Level::Info]
+    /// and standard error at [log::Level::Warn].
+    pub fn output_level(&mut self, stream: OutputStream, level: Option<log::Level>) -> &mut Self {
+        match level {
+            Some(level) => self.inner.output_levels.insert(stream, level),
+            None => self.inner.output_levels.remove(&stream),
+        };
+        self
+    }
+
+    /// Attach a backtrace of the guest stack to errors caused by the guest.
+    ///
+    /// When the guest panics or faults, its stack is unwound and symbolized
+    /// using the debug info in its ELF, and the result is stored in the
+    /// [super::FaultContext] of the [super::ExecutorError]. This only applies
+    /// to an [super::Executor] constructed with [super::Executor::from_elf].
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&mut self, enable: bool) -> &mut Self {
+        self.inner.backtrace = enable;
+        self
+    }
+
+    fn add_syscall(&mut self, syscall: SyscallName, handler: Arc<Mutex<H::Syscall>>) -> &mut Self {
+        if syscall.as_str() == SYS_RANDOM.as_str() {
+            self.inner.random_source = RandomSource::Custom;
+            self.inner.seeded_random = None;
+        }
         self.inner.syscalls.with_syscall(syscall, handler);
         self
     }
 
+    fn add_read_fd(&mut self, fd: u32, reader: Arc<Mutex<H::Reader>>) -> &mut Self {
+        self.inner.io.lock().unwrap().with_read_fd(fd, reader);
+        self
+    }
+
+    fn add_write_fd(&mut self, fd: u32, writer: Arc<Mutex<H::Writer>>) -> &mut Self {
+        self.inner.io.lock().unwrap().with_write_fd(fd, writer);
+        self
+    }
+
+    fn set_trace_callback(&mut self, callback: Arc<Mutex<H::TraceCallback>>) -> &mut Self {
+        self.inner.trace_callback = Some(callback);
+        self
+    }
+
+    fn set_syscall_recorder(&mut self, writer: Arc<Mutex<H::Writer>>) -> &mut Self {
+        self.inner.syscall_recorder = Some(Arc::new(Mutex::new(SyscallRecorder::new(writer))));
+        self
+    }
+
+    fn set_syscall_replayer(&mut self, reader: Arc<Mutex<H::Reader>>) -> &mut Self {
+        self.inner.syscall_replayer = Some(Arc::new(Mutex::new(SyscallReplayer::new(reader))));
+        self
+    }
+
+    fn set_syscall_tracer(&mut self, writer: Arc<Mutex<H::Writer>>) -> &mut Self {
+        self.inner.syscall_tracer = Some(Arc::new(Mutex::new(SyscallTracer::new(writer))));
+        self
+    }
+}
+
+impl<'a> ExecutorEnvBuilder<'a, LocalHandlers> {
+    /// Add a handler for a raw syscall implementation.
+    pub fn syscall(&mut self, syscall: SyscallName, handler: impl Syscall + 'a) -> &mut Self {
+        self.add_syscall(syscall, Arc::new(Mutex::new(handler)))
+    }
+
     /// Add a posix-style standard input.
     pub fn stdin(&mut self, reader: impl Read + 'a) -> &mut Self {
         self.read_fd(fileno::STDIN, BufReader::new(reader))
@@ -280,22 +798,19 @@ impl<'a> ExecutorEnvBuilder<'a> {
 
     /// Add a posix-style file descriptor for reading.
     pub fn read_fd(&mut self, fd: u32, reader: impl BufRead + 'a) -> &mut Self {
-        self.inner.io.borrow_mut().with_read_fd(fd, reader);
-        self
+        self.add_read_fd(fd, Arc::new(Mutex::new(reader)))
     }
 
     /// Add a posix-style file descriptor for writing.
     pub fn write_fd(&mut self, fd: u32, writer: impl Write + 'a) -> &mut Self {
-        self.inner.io.borrow_mut().with_write_fd(fd, writer);
-        self
+        self.add_write_fd(fd, Arc::new(Mutex::new(writer)))
     }
 
     /// Add a handler for a syscall which inputs and outputs a slice
     /// of plain old data. The guest can call these by invoking
     /// `risc0_zkvm::guest::env::send_recv_slice`
     pub fn slice_io(&mut self, syscall: SyscallName, handler: impl SliceIo + 'a) -> &mut Self {
-        self.syscall(syscall, handler.to_syscall());
-        self
+        self.syscall(syscall, handler.to_syscall())
     }
 
     /// Add a handler for a syscall which inputs and outputs a slice
@@ -306,8 +821,7 @@ impl<'a> ExecutorEnvBuilder<'a> {
         syscall: SyscallName,
         f: impl Fn(&[u8]) -> Vec<u8> + 'a,
     ) -> &mut Self {
-        self.slice_io(syscall, slice_io_from_fn(f));
-        self
+        self.slice_io(syscall, slice_io_from_fn(f))
     }
 
     /// Add a callback handler for raw trace messages.
@@ -315,7 +829,120 @@ impl<'a> ExecutorEnvBuilder<'a> {
         &mut self,
         callback: impl FnMut(TraceEvent) -> Result<()> + 'a,
     ) -> &mut Self {
-        self.inner.trace_callback = Some(Rc::new(RefCell::new(callback)));
-        self
+        self.set_trace_callback(Arc::new(Mutex::new(callback)))
+    }
+
+    /// Record the result of every syscall made by the guest to the given
+    /// writer.
+    ///
+    /// The log can later be passed to
+    /// [ExecutorEnvBuilder::replay_syscalls] to re-run the guest
+    /// deterministically, without access to the original host environment.
+    pub fn record_syscalls(&mut self, writer: impl Write + 'a) -> &mut Self {
+        self.set_syscall_recorder(Arc::new(Mutex::new(writer)))
+    }
+
+    /// Replay syscall results from a log written by
+    /// [ExecutorEnvBuilder::record_syscalls] instead of calling the
+    /// registered handlers.
+    ///
+    /// Writes to the journal and host-side logging still take place. If the
+    /// guest issues a syscall that doesn't match the log, execution fails with
+    /// a [super::SyscallDivergence] error.
+    pub fn replay_syscalls(&mut self, reader: impl Read + 'a) -> &mut Self {
+        self.set_syscall_replayer(Arc::new(Mutex::new(BufReader::new(reader))))
+    }
+
+    /// Write a trace of every syscall made by the guest to the given writer,
+    /// as JSON lines.
+    ///
+    /// Each line is a [super::SyscallTraceEntry], which records the name,
+    /// cycle and results of the syscall, along with the guest memory read by
+    /// its handler.
+    pub fn trace_syscalls(&mut self, writer: impl Write + 'a) -> &mut Self {
+        self.set_syscall_tracer(Arc::new(Mutex::new(writer)))
+    }
+}
+
+impl<'a> ExecutorEnvBuilder<'a, SendHandlers> {
+    /// Like [ExecutorEnvBuilder::syscall], but for a handler which can be
+    /// sent to other threads.
+    pub fn syscall_send(
+        &mut self,
+        syscall: SyscallName,
+        handler: impl Syscall + Send + 'a,
+    ) -> &mut Self {
+        self.add_syscall(syscall, Arc::new(Mutex::new(handler)))
+    }
+
+    /// Like [ExecutorEnvBuilder::stdin], but for a reader which can be sent to
+    /// other threads.
+    pub fn stdin_send(&mut self, reader: impl Read + Send + 'a) -> &mut Self {
+        self.read_fd_send(fileno::STDIN, BufReader::new(reader))
+    }
+
+    /// Like [ExecutorEnvBuilder::stdout], but for a writer which can be sent
+    /// to other threads.
+    pub fn stdout_send(&mut self, writer: impl Write + Send + 'a) -> &mut Self {
+        self.write_fd_send(fileno::STDOUT, writer)
+    }
+
+    /// Like [ExecutorEnvBuilder::read_fd], but for a reader which can be sent
+    /// to other threads.
+    pub fn read_fd_send(&mut self, fd: u32, reader: impl BufRead + Send + 'a) -> &mut Self {
+        self.add_read_fd(fd, Arc::new(Mutex::new(reader)))
+    }
+
+    /// Like [ExecutorEnvBuilder::write_fd], but for a writer which can be
+    /// sent to other threads.
+    pub fn write_fd_send(&mut self, fd: u32, writer: impl Write + Send + 'a) -> &mut Self {
+        self.add_write_fd(fd, Arc::new(Mutex::new(writer)))
+    }
+
+    /// Like [ExecutorEnvBuilder::slice_io], but for a handler which can be
+    /// sent to other threads.
+    pub fn slice_io_send(
+        &mut self,
+        syscall: SyscallName,
+        handler: impl SliceIo + Send + 'a,
+    ) -> &mut Self {
+        self.syscall_send(syscall, handler.to_syscall())
+    }
+
+    /// Like [ExecutorEnvBuilder::io_callback], but for a callback which can be
+    /// sent to other threads.
+    pub fn io_callback_send(
+        &mut self,
+        syscall: SyscallName,
+        f: impl Fn(&[u8]) -> Vec<u8> + Send + 'a,
+    ) -> &mut Self {
+        self.slice_io_send(syscall, slice_io_from_fn(f))
+    }
+
+    /// Like [ExecutorEnvBuilder::trace_callback], but for a callback which can
+    /// be sent to other threads.
+    pub fn trace_callback_send(
+        &mut self,
+        callback: impl FnMut(TraceEvent) -> Result<()> + Send + 'a,
+    ) -> &mut Self {
+        self.set_trace_callback(Arc::new(Mutex::new(callback)))
+    }
+
+    /// Like [ExecutorEnvBuilder::record_syscalls], but for a writer which can
+    /// be sent to other threads.
+    pub fn record_syscalls_send(&mut self, writer: impl Write + Send + 'a) -> &mut Self {
+        self.set_syscall_recorder(Arc::new(Mutex::new(writer)))
+    }
+
+    /// Like [ExecutorEnvBuilder::replay_syscalls], but for a reader which can
+    /// be sent to other threads.
+    pub fn replay_syscalls_send(&mut self, reader: impl Read + Send + 'a) -> &mut Self {
+        self.set_syscall_replayer(Arc::new(Mutex::new(BufReader::new(reader))))
+    }
+
+    /// Like [ExecutorEnvBuilder::trace_syscalls], but for a writer which can
+    /// be sent to other threads.
+    pub fn trace_syscalls_send(&mut self, writer: impl Write + Send + 'a) -> &mut Self {
+        self.set_syscall_tracer(Arc::new(Mutex::new(writer)))
     }
 }
@@ -0,0 +1,452 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A stub for the GDB remote serial protocol.
+//!
+//! This allows a debugger such as `riscv32-unknown-elf-gdb` to attach to a
+//! guest running within the [Executor] using `target remote`. The stub
+//! supports reading and writing registers and memory, breakpoints,
+//! single-stepping and continuing.
+
+use std::io::{ErrorKind, Read, Write};
+
+use anyhow::{anyhow, bail, Result};
+use risc0_zkvm_platform::{memory::MEM_SIZE, syscall::reg_abi::REG_MAX};
+
+use super::{Executor, Handlers, StopReason, WatchAccess};
+use crate::{receipt::ExitCode, Segment, SegmentRef, Session, SimpleSegmentRef};
+
+/// The register numbering used by GDB: x0 through x31, followed by the pc.
+const REG_PC: usize = REG_MAX;
+
+const REG_NAMES: [&str; REG_MAX] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// The largest packet the stub will accept, as advertised to the debugger.
+const PACKET_SIZE: usize = 0x4000;
+
+/// The stop reply for a trap (SIGTRAP).
+const STOP_TRAP: &str = "S05";
+
+impl<'a, H: Handlers<'a>> Executor<'a, H> {
+    /// Run the executor under the control of a debugger speaking the GDB
+    /// remote serial protocol over `conn`, producing a [Session] once the
+    /// guest reaches [ExitCode::Paused] or [ExitCode::Halted].
+    ///
+    /// The guest is stopped before its first instruction, waiting for the
+    /// debugger. If the debugger detaches, the guest runs to completion.
+    ///
+    /// Note that a [Session] in which the debugger has modified registers or
+    /// memory can not be proven.
+    pub fn run_with_gdb(&mut self, conn: impl Read + Write) -> Result<Session> {
+        self.start_run()?;
+        let mut callback = |segment: Segment| -> Result<Box<dyn SegmentRef>> {
+            Ok(Box::new(SimpleSegmentRef::new(segment)))
+        };
+        let mut stub = GdbStub::new(conn);
+        match stub.serve(self, &mut callback)? {
+            Some(session) => Ok(session),
+            None => self.run_with_callback(callback),
+        }
+    }
+
+    // Execute exactly one instruction, closing any segments along the way.
+    // Returns the exit code of the run if execution has stopped.
+    fn step_insn<F>(&mut self, callback: &mut F) -> Result<Option<ExitCode>>
+    where
+        F: FnMut(Segment) -> Result<Box<dyn SegmentRef>>,
+    {
+        loop {
+            match self.step()? {
+                None => {
+                    self.skip_breakpoint = false;
+                    return Ok(None);
+                }
+                // A split happens before the instruction executes, so try again
+                // in the new segment.
+                Some(exit_code) => {
+                    if let Some(exit_code) = self.finish_segment(exit_code, callback)? {
+                        return Ok(Some(exit_code));
+                    }
+                }
+            }
+        }
+    }
+
+    fn gdb_load_register(&self, idx: usize) -> u32 {
+        match idx {
+            REG_PC => self.pc,
+            idx => self.monitor.load_register(idx),
+        }
+    }
+
+    fn gdb_store_register(&mut self, idx: usize, value: u32) {
+        match idx {
+            REG_PC => {
+                self.pc = value;
+                self.skip_breakpoint = false;
+            }
+            0 => (),
+            idx => {
+                self.monitor.store_register(idx, value);
+                self.monitor.commit(self.session_cycle());
+            }
+        }
+    }
+}
+
+enum Action {
+    Reply(String),
+    Resume(Resume),
+    Detach,
+    Kill,
+}
+
+enum Resume {
+    Step,
+    Continue,
+}
+
+struct GdbStub<S> {
+    conn: S,
+    no_ack: bool,
+}
+
+impl<S: Read + Write> GdbStub<S> {
+    fn new(conn: S) -> Self {
+        Self {
+            conn,
+            no_ack: false,
+        }
+    }
+
+    // Serve requests until the guest exits (returning its session) or the
+    // debugger goes away (returning None).
+    fn serve<F>(&mut self, exec: &mut Executor, callback: &mut F) -> Result<Option<Session>>
+    where
+        F: FnMut(Segment) -> Result<Box<dyn SegmentRef>>,
+    {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(Ok(packet)) => packet,
+                Some(Err(err)) => {
+                    log::debug!("gdb: {err:?}");
+                    self.write_packet("E01")?;
+                    continue;
+                }
+                None => {
+                    log::debug!("gdb: connection closed");
+                    return Ok(None);
+                }
+            };
+            log::trace!("gdb <- {packet}");
+            let action = match self.handle(exec, &packet) {
+                Ok(action) => action,
+                Err(err) => {
+                    log::debug!("gdb: {err:?}");
+                    Action::Reply("E01".to_string())
+                }
+            };
+            let stop = match action {
+                Action::Reply(reply) => {
+                    self.write_packet(&reply)?;
+                    continue;
+                }
+                Action::Detach => {
+                    self.write_packet("OK")?;
+                    return Ok(None);
+                }
+                Action::Kill => bail!("Guest killed by the debugger"),
+                Action::Resume(Resume::Continue) => {
+                    exec.run_until_stop_with_callback(&mut *callback)?
+                }
+                Action::Resume(Resume::Step) => match exec.step_insn(callback)? {
+                    Some(exit_code) => StopReason::Exited(exec.finish_run(exit_code)),
+                    None => match exec.monitor.watch_hit.take() {
+                        Some(hit) => StopReason::Watchpoint {
+                            pc: exec.pc,
+                            addr: hit.addr,
+                            access: hit.access,
+                        },
+                        None => StopReason::Breakpoint { pc: exec.pc },
+                    },
+                },
+            };
+            match stop {
+                StopReason::Breakpoint { .. } => self.write_packet(STOP_TRAP)?,
+                StopReason::Watchpoint { addr, access, .. } => {
+                    let kind = match access {
+                        WatchAccess::Read => "rwatch",
+                        WatchAccess::Write => "watch",
+                        WatchAccess::ReadWrite => "awatch",
+                    };
+                    self.write_packet(&format!("T05{kind}:{addr:x};"))?
+                }
+                StopReason::Exited(session) => {
+                    let status = match session.exit_code {
+                        ExitCode::Halted(code) | ExitCode::Paused(code) => code,
+                        ExitCode::SystemSplit | ExitCode::SessionLimit => unreachable!(),
+                    };
+                    self.write_packet(&format!("W{:02x}", status as u8))?;
+                    return Ok(Some(session));
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, exec: &mut Executor, packet: &str) -> Result<Action> {
+        let reply = |reply: &str| Ok(Action::Reply(reply.to_string()));
+        let (cmd, args) = match packet.get(..1) {
+            Some(cmd) => (cmd, &packet[1..]),
+            None => return reply(""),
+        };
+        match cmd {
+            "?" => reply(STOP_TRAP),
+            "g" => reply(
+                &(0..=REG_PC)
+                    .map(|idx| to_hex(&exec.gdb_load_register(idx).to_le_bytes()))
+                    .collect::<String>(),
+            ),
+            "G" => {
+                let bytes = from_hex(args)?;
+                for (idx, chunk) in bytes.chunks_exact(4).take(REG_PC + 1).enumerate() {
+                    exec.gdb_store_register(idx, u32::from_le_bytes(chunk.try_into()?));
+                }
+                reply("OK")
+            }
+            "p" => {
+                let idx = parse_reg(args)?;
+                reply(&to_hex(&exec.gdb_load_register(idx).to_le_bytes()))
+            }
+            "P" => {
+                let (idx, value) = args.split_once('=').ok_or(anyhow!("malformed P"))?;
+                let value = from_hex(value)?;
+                exec.gdb_store_register(parse_reg(idx)?, u32::from_le_bytes(value[..].try_into()?));
+                reply("OK")
+            }
+            "m" => {
+                let (addr, len) = parse_pair(args)?;
+                reply(&to_hex(&exec.read_memory(addr, len)?))
+            }
+            "M" => {
+                let (region, data) = args.split_once(':').ok_or(anyhow!("malformed M"))?;
+                let (addr, len) = parse_addr_len(region)?;
+                let bytes = from_hex(data)?;
+                if bytes.len() != len as usize {
+                    bail!("length mismatch in M");
+                }
+                exec.monitor.store_region(addr, &bytes);
+                exec.monitor.commit(exec.session_cycle());
+                reply("OK")
+            }
+            "Z" | "z" => {
+                let (kind, args) = args.split_once(',').ok_or(anyhow!("malformed {cmd}"))?;
+                let (addr, len) = parse_addr_len(args)?;
+                // Breakpoints don't modify guest memory, so software and
+                // hardware breakpoints are treated the same.
+                let access = match kind {
+                    "0" | "1" => None,
+                    "2" => Some(WatchAccess::Write),
+                    "3" => Some(WatchAccess::Read),
+                    "4" => Some(WatchAccess::ReadWrite),
+                    _ => return reply(""),
+                };
+                match (cmd, access) {
+                    ("Z", None) => exec.add_breakpoint(addr),
+                    ("Z", Some(access)) => exec.add_watchpoint(addr..addr + len, access),
+                    (_, None) => exec.remove_breakpoint(addr),
+                    (_, Some(_)) => exec.remove_watchpoint(addr..addr + len),
+                }
+                reply("OK")
+            }
+            "s" | "c" => {
+                if !args.is_empty() {
+                    exec.gdb_store_register(REG_PC, parse_hex(args)?);
+                }
+                Ok(Action::Resume(match cmd {
+                    "s" => Resume::Step,
+                    _ => Resume::Continue,
+                }))
+            }
+            "k" => Ok(Action::Kill),
+            "D" => Ok(Action::Detach),
+            "H" => reply("OK"),
+            "q" | "Q" | "v" => self.handle_query(packet),
+            _ => reply(""),
+        }
+    }
+
+    fn handle_query(&mut self, packet: &str) -> Result<Action> {
+        let reply = |reply: &str| Ok(Action::Reply(reply.to_string()));
+        if packet.starts_with("qSupported") {
+            return reply(&format!(
+                "PacketSize={PACKET_SIZE:x};QStartNoAckMode+;qXfer:features:read+"
+            ));
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = parse_pair(args)?;
+            let xml = target_xml();
+            let start = (offset as usize).min(xml.len());
+            let end = (start + len as usize).min(xml.len());
+            let prefix = if end < xml.len() { "m" } else { "l" };
+            return reply(&format!("{prefix}{}", &xml[start..end]));
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                reply("OK")
+            }
+            "qAttached" => reply("1"),
+            "qC" => reply("QC1"),
+            "qfThreadInfo" => reply("m1"),
+            "qsThreadInfo" => reply("l"),
+            _ => reply(""),
+        }
+    }
+
+    // Read the next packet, acknowledging it unless acks have been disabled.
+    // Acks from the debugger and interrupt requests are skipped. Returns None
+    // when the connection has been closed, and an inner error for a packet
+    // which is too large or isn't valid UTF-8.
+    fn read_packet(&mut self) -> Result<Option<Result<String>>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => break,
+                Some(_) => continue,
+            }
+        }
+        let mut data = Vec::new();
+        let mut sum = 0u8;
+        let mut too_large = false;
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                // Keep reading the rest of an oversized packet, so that the
+                // stream stays in sync.
+                Some(byte) => {
+                    sum = sum.wrapping_add(byte);
+                    if data.len() < PACKET_SIZE {
+                        data.push(byte);
+                    } else {
+                        too_large = true;
+                    }
+                }
+            }
+        }
+        let mut checksum = [0; 2];
+        self.conn.read_exact(&mut checksum)?;
+        let checksum = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+        if checksum != Some(sum) {
+            log::debug!("gdb: bad checksum");
+            if !self.no_ack {
+                self.conn.write_all(b"-")?;
+            }
+            return self.read_packet();
+        }
+        if !self.no_ack {
+            self.conn.write_all(b"+")?;
+        }
+        if too_large {
+            return Ok(Some(Err(anyhow!("gdb packet too large"))));
+        }
+        Ok(Some(String::from_utf8(data).map_err(Into::into)))
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        let mut byte = [0];
+        match self.conn.read_exact(&mut byte) {
+            Ok(()) => Ok(Some(byte[0])),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> Result<()> {
+        log::trace!("gdb -> {data}");
+        let checksum = compute_checksum(data.as_bytes());
+        write!(self.conn, "${data}#{checksum:02x}")?;
+        self.conn.flush()?;
+        Ok(())
+    }
+}
+
+fn target_xml() -> String {
+    let mut regs: Vec<String> = REG_NAMES
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let ty = match idx {
+                1 => "code_ptr",
+                2 | 8 => "data_ptr",
+                _ => "int",
+            };
+            format!(r#"<reg name="{name}" bitsize="32" type="{ty}" regnum="{idx}"/>"#)
+        })
+        .collect();
+    regs.push(format!(
+        r#"<reg name="pc" bitsize="32" type="code_ptr" regnum="{REG_PC}"/>"#
+    ));
+    format!(
+        concat!(
+            r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd">"#,
+            r#"<target version="1.0"><architecture>riscv:rv32</architecture>"#,
+            r#"<feature name="org.gnu.gdb.riscv.cpu">{}</feature></target>"#
+        ),
+        regs.concat()
+    )
+}
+
+fn compute_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>> {
+    hex::decode(s).map_err(|err| anyhow!("{err}"))
+}
+
+fn parse_hex(s: &str) -> Result<u32> {
+    Ok(u32::from_str_radix(s, 16)?)
+}
+
+fn parse_reg(s: &str) -> Result<usize> {
+    let idx = parse_hex(s)? as usize;
+    if idx > REG_PC {
+        bail!("invalid register: {idx}");
+    }
+    Ok(idx)
+}
+
+fn parse_pair(s: &str) -> Result<(u32, u32)> {
+    let (lhs, rhs) = s.split_once(',').ok_or(anyhow!("malformed pair: {s}"))?;
+    Ok((parse_hex(lhs)?, parse_hex(rhs)?))
+}
+
+fn parse_addr_len(s: &str) -> Result<(u32, u32)> {
+    let (addr, len) = parse_pair(s)?;
+    match addr.checked_add(len) {
+        Some(end) if end as usize <= MEM_SIZE => Ok((addr, len)),
+        _ => bail!("address out of range: 0x{addr:08x}"),
+    }
+}
@@ -17,11 +17,11 @@
 use std::{
     cell::RefCell,
     collections::{BTreeMap, HashMap},
-    io::{stderr, stdin, stdout, BufRead, BufReader, Write},
+    io::{stderr, stdin, stdout, BufRead, BufReader, Seek, SeekFrom, Write},
     marker::PhantomData,
     mem::take,
     ops::DerefMut,
-    rc::Rc,
+    sync::{Arc, Mutex},
 };
 
 use anyhow::{bail, Result};
@@ -30,14 +30,20 @@ use risc0_zkvm_platform::{
     fileno,
     syscall::{
         nr::{
-            SYS_CYCLE_COUNT, SYS_LOG, SYS_PANIC, SYS_RANDOM, SYS_READ, SYS_READ_AVAIL, SYS_WRITE,
+            SYS_CLOSE, SYS_CYCLE_COUNT, SYS_LOG, SYS_OPEN, SYS_PANIC, SYS_RANDOM, SYS_READ,
+            SYS_READ_AVAIL, SYS_SEEK, SYS_STAT, SYS_WRITE,
         },
         reg_abi::{REG_A3, REG_A4, REG_A5},
-        SyscallName,
+        seek, SyscallName,
     },
     WORD_SIZE,
 };
 
+use super::{
+    env::Handlers,
+    vfs::{MountSource, Vfs, VfsFile},
+};
+
 /// A host-side implementation of a system call.
 pub trait Syscall {
     /// Invokes the system call.
@@ -118,7 +124,7 @@ pub trait SliceIo: Sized {
 /// A wrapper around a SliceIo that exposes it as a Syscall handler.
 pub struct SliceIoSyscall<H: SliceIo> {
     handler: H,
-    stored_result: RefCell<Option<Vec<H::ToGuest>>>,
+    stored_result: RefCell<Option<Vec<u8>>>,
 }
 
 impl<H: SliceIo> SliceIoSyscall<H> {
@@ -151,14 +157,14 @@ impl<H: SliceIo> Syscall for SliceIoSyscall<H> {
                 let from_guest: &[H::FromGuest] = bytemuck::cast_slice(from_guest);
                 let result = self.handler.handle_io(syscall, from_guest);
                 let len = result.len();
-                *stored_result = Some(result);
+                *stored_result = Some(bytemuck::cast_slice(result.as_slice()).to_vec());
                 Ok((len as u32, 0))
             }
             Some(stored) => {
                 // Second call of pair. We already have data to send
                 // to the guest; send it to the buffer that the guest
                 // allocated.
-                let stored_bytes: &[u8] = bytemuck::cast_slice(stored.as_slice());
+                let stored_bytes: &[u8] = stored.as_slice();
                 let to_guest_bytes: &mut [u8] = bytemuck::cast_slice_mut(to_guest);
                 if core::mem::size_of::<H::ToGuest>() < WORD_SIZE {
                     assert!(stored_bytes.len() <= to_guest_bytes.len());
@@ -183,7 +189,8 @@ pub fn slice_io_from_fn<T: Pod, U: Pod, F: Fn(&[T]) -> Vec<U>>(f: F) -> impl Sli
 
 struct FnWrapper<T: Pod, U: Pod, F: Fn(&[T]) -> Vec<U>> {
     f: F,
-    phantom: PhantomData<(T, U)>,
+    // Only the closure is held, so this is Send and Sync whenever F is.
+    phantom: PhantomData<fn(&[T]) -> Vec<U>>,
 }
 
 impl<T: Pod, U: Pod, F: Fn(&[T]) -> Vec<U>> SliceIo for FnWrapper<T, U, F> {
@@ -195,31 +202,136 @@ impl<T: Pod, U: Pod, F: Fn(&[T]) -> Vec<U>> SliceIo for FnWrapper<T, U, F> {
     }
 }
 
+/// The most files the guest may have open from the virtual filesystem at once.
+pub(crate) const MAX_OPEN_FILES: usize = 64;
+
 /// Posix-style I/O
-#[derive(Clone)]
-pub struct PosixIo<'a> {
-    read_fds: BTreeMap<u32, Rc<RefCell<dyn BufRead + 'a>>>,
-    write_fds: BTreeMap<u32, Rc<RefCell<dyn Write + 'a>>>,
+pub struct PosixIo<'a, H: Handlers<'a>> {
+    read_fds: BTreeMap<u32, Arc<Mutex<H::Reader>>>,
+    write_fds: BTreeMap<u32, Arc<Mutex<H::Writer>>>,
+    vfs: Vfs,
+    // Files opened from the virtual filesystem, which are also in read_fds.
+    vfs_fds: BTreeMap<u32, Arc<Mutex<Box<dyn VfsFile>>>>,
 }
 
-impl<'a> PosixIo<'a> {
-    pub fn with_read_fd(&mut self, fd: u32, reader: impl BufRead + 'a) -> &mut Self {
-        self.read_fds.insert(fd, Rc::new(RefCell::new(reader)));
+impl<'a, H: Handlers<'a>> Clone for PosixIo<'a, H> {
+    fn clone(&self) -> Self {
+        Self {
+            read_fds: self.read_fds.clone(),
+            write_fds: self.write_fds.clone(),
+            vfs: self.vfs.clone(),
+            vfs_fds: self.vfs_fds.clone(),
+        }
+    }
+}
+
+impl<'a, H: Handlers<'a>> PosixIo<'a, H> {
+    pub fn with_read_fd(&mut self, fd: u32, reader: Arc<Mutex<H::Reader>>) -> &mut Self {
+        self.read_fds.insert(fd, reader);
         self
     }
 
-    pub fn with_write_fd(&mut self, fd: u32, writer: impl Write + 'a) -> &mut Self {
-        self.write_fds.insert(fd, Rc::new(RefCell::new(writer)));
+    pub fn with_write_fd(&mut self, fd: u32, writer: Arc<Mutex<H::Writer>>) -> &mut Self {
+        self.write_fds.insert(fd, writer);
         self
     }
 
+    pub fn mount(&mut self, path: &str, source: MountSource) -> Result<&mut Self> {
+        self.vfs.mount(path, source)?;
+        Ok(self)
+    }
+
+    fn sys_open(&mut self, ctx: &mut dyn SyscallContext) -> Result<(u32, u32)> {
+        let path_ptr = ctx.load_register(REG_A3);
+        let path_len = ctx.load_register(REG_A4);
+        let path = String::from_utf8(ctx.load_region(path_ptr, path_len))?;
+        if self.vfs_fds.len() >= MAX_OPEN_FILES {
+            log::debug!("sys_open, unable to open {path}: too many open files");
+            return Ok((u32::MAX, 0));
+        }
+        let file = match self.vfs.open(&path) {
+            Ok(file) => Arc::new(Mutex::new(file)),
+            Err(err) => {
+                log::debug!("sys_open, unable to open {path}: {err}");
+                return Ok((u32::MAX, 0));
+            }
+        };
+        let fd = (fileno::JOURNAL + 1..)
+            .find(|fd| !self.read_fds.contains_key(fd) && !self.write_fds.contains_key(fd))
+            .unwrap();
+        log::debug!("sys_open, opened {path} as fd {fd}");
+        self.read_fds.insert(fd, H::reader(file.clone()));
+        self.vfs_fds.insert(fd, file);
+        Ok((fd, 0))
+    }
+
+    fn sys_close(&mut self, ctx: &mut dyn SyscallContext) -> Result<(u32, u32)> {
+        let fd = ctx.load_register(REG_A3);
+        if self.vfs_fds.remove(&fd).is_none() {
+            log::debug!("sys_close, bad virtual file descriptor {fd}");
+            return Ok((u32::MAX, 0));
+        }
+        log::debug!("sys_close, closed fd {fd}");
+        self.read_fds.remove(&fd);
+        Ok((0, 0))
+    }
+
+    fn vfs_file(&self, syscall: &str, fd: u32) -> Option<&Arc<Mutex<Box<dyn VfsFile>>>> {
+        let file = self.vfs_fds.get(&fd);
+        if file.is_none() {
+            log::debug!("{syscall}, bad virtual file descriptor {fd}");
+        }
+        file
+    }
+
+    fn sys_seek(&mut self, ctx: &mut dyn SyscallContext) -> Result<(u32, u32)> {
+        let fd = ctx.load_register(REG_A3);
+        let offset = ctx.load_register(REG_A4) as i32;
+        let whence = ctx.load_register(REG_A5);
+        let pos = match whence {
+            seek::SET if offset >= 0 => SeekFrom::Start(offset as u64),
+            seek::SET => return Ok((u32::MAX, 0)),
+            seek::CUR => SeekFrom::Current(offset as i64),
+            seek::END => SeekFrom::End(offset as i64),
+            _ => {
+                log::debug!("sys_seek, bad seek origin {whence}");
+                return Ok((u32::MAX, 0));
+            }
+        };
+        let Some(file) = self.vfs_file("sys_seek", fd) else {
+            return Ok((u32::MAX, 0));
+        };
+        let result = file.lock().unwrap().seek(pos);
+        match result.ok().and_then(|pos| u32::try_from(pos).ok()) {
+            Some(pos) if pos != u32::MAX => Ok((pos, 0)),
+            _ => Ok((u32::MAX, 0)),
+        }
+    }
+
+    fn sys_stat(&mut self, ctx: &mut dyn SyscallContext) -> Result<(u32, u32)> {
+        let fd = ctx.load_register(REG_A3);
+        let Some(file) = self.vfs_file("sys_stat", fd) else {
+            return Ok((u32::MAX, 0));
+        };
+        let mut file = file.lock().unwrap();
+        let size = file.stream_position().and_then(|pos| {
+            let size = file.seek(SeekFrom::End(0))?;
+            file.seek(SeekFrom::Start(pos))?;
+            Ok(size)
+        });
+        match size.ok().and_then(|size| u32::try_from(size).ok()) {
+            Some(size) if size != u32::MAX => Ok((size, 0)),
+            _ => Ok((u32::MAX, 0)),
+        }
+    }
+
     fn sys_read_avail(&mut self, ctx: &mut dyn SyscallContext) -> Result<(u32, u32)> {
         let fd = ctx.load_register(REG_A3);
         let reader = self
             .read_fds
             .get_mut(&fd)
             .expect(&format!("Bad read file descriptor {fd}"));
-        let navail = reader.borrow_mut().fill_buf().unwrap().len() as u32;
+        let navail = reader.lock().unwrap().fill_buf().unwrap().len() as u32;
         log::debug!("navail: {navail}");
         Ok((navail, 0))
     }
@@ -248,7 +360,7 @@ impl<'a> PosixIo<'a> {
         let read_all = |mut buf: &mut [u8]| -> usize {
             let mut tot_nread = 0;
             while !buf.is_empty() {
-                let nread = reader.borrow_mut().read(buf).unwrap();
+                let nread = reader.lock().unwrap().read(buf).unwrap();
                 if nread == 0 {
                     break;
                 }
@@ -300,27 +412,33 @@ impl<'a> PosixIo<'a> {
         log::debug!("Writing {buf_len} bytes to file descriptor {fd}");
 
         writer
-            .borrow_mut()
+            .lock()
+            .unwrap()
             .write_all(from_guest_bytes.as_slice())
             .unwrap();
         Ok((0, 0))
     }
 }
 
-impl<'a> Default for PosixIo<'a> {
+impl<'a, H: Handlers<'a>> Default for PosixIo<'a, H> {
     fn default() -> Self {
         let mut new = Self {
             read_fds: Default::default(),
             write_fds: Default::default(),
+            vfs: Default::default(),
+            vfs_fds: Default::default(),
         };
-        new.with_read_fd(fileno::STDIN, BufReader::new(stdin()))
-            .with_write_fd(fileno::STDOUT, stdout())
-            .with_write_fd(fileno::STDERR, stderr());
+        new.with_read_fd(
+            fileno::STDIN,
+            H::reader(Arc::new(Mutex::new(BufReader::new(stdin())))),
+        )
+        .with_write_fd(fileno::STDOUT, H::writer(Arc::new(Mutex::new(stdout()))))
+        .with_write_fd(fileno::STDERR, H::writer(Arc::new(Mutex::new(stderr()))));
         new
     }
 }
 
-impl<'a> Syscall for PosixIo<'a> {
+impl<'a, H: Handlers<'a>> Syscall for PosixIo<'a, H> {
     fn syscall(
         &mut self,
         syscall: &str,
@@ -334,45 +452,69 @@ impl<'a> Syscall for PosixIo<'a> {
             self.sys_read(ctx, to_guest)
         } else if syscall == SYS_WRITE.as_str() {
             self.sys_write(ctx)
+        } else if syscall == SYS_OPEN.as_str() {
+            self.sys_open(ctx)
+        } else if syscall == SYS_CLOSE.as_str() {
+            self.sys_close(ctx)
+        } else if syscall == SYS_SEEK.as_str() {
+            self.sys_seek(ctx)
+        } else if syscall == SYS_STAT.as_str() {
+            self.sys_stat(ctx)
         } else {
             bail!("Unknown syscall {syscall}")
         }
     }
 }
 
-impl<'a> Syscall for Rc<RefCell<PosixIo<'a>>> {
+impl<'a, H: Handlers<'a>> Syscall for Arc<Mutex<PosixIo<'a, H>>> {
     fn syscall(
         &mut self,
         syscall: &str,
         ctx: &mut dyn SyscallContext,
         to_guest: &mut [u32],
     ) -> Result<(u32, u32)> {
-        self.borrow_mut().syscall(syscall, ctx, to_guest)
+        self.lock().unwrap().syscall(syscall, ctx, to_guest)
     }
 }
 
-#[derive(Clone)]
-pub(crate) struct SyscallTable<'a> {
-    pub(crate) inner: HashMap<String, Rc<RefCell<dyn Syscall + 'a>>>,
+pub(crate) struct SyscallTable<'a, H: Handlers<'a>> {
+    pub(crate) inner: HashMap<String, Arc<Mutex<H::Syscall>>>,
+}
+
+impl<'a, H: Handlers<'a>> Clone for SyscallTable<'a, H> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
 }
 
-impl<'a> Default for SyscallTable<'a> {
+impl<'a, H: Handlers<'a>> Default for SyscallTable<'a, H> {
     fn default() -> Self {
         let mut new = Self {
             inner: Default::default(),
         };
-        new.with_syscall(SYS_CYCLE_COUNT, syscalls::CycleCount)
-            .with_syscall(SYS_LOG, syscalls::Log)
-            .with_syscall(SYS_PANIC, syscalls::Panic)
-            .with_syscall(SYS_RANDOM, syscalls::Random);
+        new.with_syscall(
+            SYS_CYCLE_COUNT,
+            H::syscall(Arc::new(Mutex::new(syscalls::CycleCount))),
+        )
+        .with_syscall(SYS_LOG, H::syscall(Arc::new(Mutex::new(syscalls::Log))))
+        .with_syscall(SYS_PANIC, H::syscall(Arc::new(Mutex::new(syscalls::Panic))))
+        .with_syscall(
+            SYS_RANDOM,
+            H::syscall(Arc::new(Mutex::new(syscalls::Random::default()))),
+        );
         new
     }
 }
 
-impl<'a> SyscallTable<'a> {
-    pub fn with_syscall(&mut self, syscall: SyscallName, handler: impl Syscall + 'a) -> &mut Self {
-        self.inner
-            .insert(syscall.as_str().to_string(), Rc::new(RefCell::new(handler)));
+impl<'a, H: Handlers<'a>> SyscallTable<'a, H> {
+    pub fn with_syscall(
+        &mut self,
+        syscall: SyscallName,
+        handler: Arc<Mutex<H::Syscall>>,
+    ) -> &mut Self {
+        self.inner.insert(syscall.as_str().to_string(), handler);
         self
     }
 }
@@ -380,13 +522,17 @@ impl<'a> SyscallTable<'a> {
 pub(crate) mod syscalls {
     use std::{cmp::min, collections::HashMap, str::from_utf8};
 
-    use anyhow::{bail, Result};
+    use anyhow::Result;
+    use rand::{RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
     use risc0_zkvm_platform::{
         syscall::reg_abi::{REG_A3, REG_A4},
         WORD_SIZE,
     };
+    use serde::{Deserialize, Serialize};
 
     use super::{Syscall, SyscallContext};
+    use crate::session::RandomSource;
 
     pub(crate) struct CycleCount;
     impl Syscall for CycleCount {
@@ -425,6 +571,30 @@ pub(crate) mod syscalls {
         }
     }
 
+    pub(crate) struct ReadNamed(pub HashMap<String, Vec<u32>>);
+    impl Syscall for ReadNamed {
+        fn syscall(
+            &mut self,
+            _syscall: &str,
+            ctx: &mut dyn SyscallContext,
+            to_guest: &mut [u32],
+        ) -> Result<(u32, u32)> {
+            let buf_ptr = ctx.load_register(REG_A3);
+            let buf_len = ctx.load_register(REG_A4);
+            let from_guest = ctx.load_region(buf_ptr, buf_len);
+            let name = from_utf8(&from_guest)?;
+
+            match self.0.get(name) {
+                None => Ok((u32::MAX, 0)),
+                Some(val) => {
+                    let nwords = min(to_guest.len(), val.len());
+                    to_guest[0..nwords].clone_from_slice(&val[0..nwords]);
+                    Ok((val.len() as u32, 0))
+                }
+            }
+        }
+    }
+
     pub(crate) struct Log;
     impl Syscall for Log {
         fn syscall(
@@ -442,6 +612,19 @@ pub(crate) mod syscalls {
         }
     }
 
+    /// The error returned by [Panic], which the executor reports as
+    /// [crate::ExecutorError::GuestPanic].
+    #[derive(Debug)]
+    pub(crate) struct GuestPanic(pub String);
+
+    impl core::fmt::Display for GuestPanic {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            write!(f, "Guest panicked: {}", self.0)
+        }
+    }
+
+    impl std::error::Error for GuestPanic {}
+
     pub(crate) struct Panic;
     impl Syscall for Panic {
         fn syscall(
@@ -454,11 +637,52 @@ pub(crate) mod syscalls {
             let buf_len = ctx.load_register(REG_A4);
             let from_guest = ctx.load_region(buf_ptr, buf_len);
             let msg = from_utf8(&from_guest)?;
-            bail!("Guest panicked: {msg}");
+            Err(GuestPanic(msg.to_string()).into())
+        }
+    }
+
+    #[derive(Default)]
+    pub(crate) struct Random {
+        // Without a seed, randomness comes from the operating system.
+        rng: Option<ChaCha20Rng>,
+    }
+
+    /// The state of a seeded [Random], from which it can be restored to
+    /// continue generating the same sequence of numbers.
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    pub(crate) struct RandomState {
+        seed: [u8; 32],
+        word_pos: u128,
+    }
+
+    impl Random {
+        pub fn seeded(seed: u64) -> Self {
+            Self {
+                rng: Some(ChaCha20Rng::seed_from_u64(seed)),
+            }
+        }
+
+        pub fn from_state(state: &RandomState) -> Self {
+            let mut rng = ChaCha20Rng::from_seed(state.seed);
+            rng.set_word_pos(state.word_pos);
+            Self { rng: Some(rng) }
+        }
+
+        pub fn state(&self) -> Option<RandomState> {
+            self.rng.as_ref().map(|rng| RandomState {
+                seed: rng.get_seed(),
+                word_pos: rng.get_word_pos(),
+            })
+        }
+
+        pub fn source(&self) -> RandomSource {
+            match self.rng {
+                Some(_) => RandomSource::Seeded,
+                None => RandomSource::Os,
+            }
         }
     }
 
-    pub(crate) struct Random;
     impl Syscall for Random {
         fn syscall(
             &mut self,
@@ -468,7 +692,10 @@ pub(crate) mod syscalls {
         ) -> Result<(u32, u32)> {
             log::debug!("SYS_RANDOM: {}", to_guest.len());
             let mut rand_buf = vec![0u8; to_guest.len() * WORD_SIZE];
-            getrandom::getrandom(rand_buf.as_mut_slice())?;
+            match self.rng.as_mut() {
+                Some(rng) => rng.fill_bytes(rand_buf.as_mut_slice()),
+                None => getrandom::getrandom(rand_buf.as_mut_slice())?,
+            }
             bytemuck::cast_slice_mut(to_guest).clone_from_slice(rand_buf.as_slice());
             Ok((0, 0))
         }
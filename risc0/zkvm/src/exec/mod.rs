@@ -18,15 +18,36 @@
 //! one or more [Segment]s, each of which contains an execution trace of the
 //! specified program.
 
+mod backtrace;
+#[cfg(feature = "coverage")]
+pub(crate) mod coverage;
 mod env;
+#[cfg(feature = "gdbstub")]
+mod gdbstub;
 pub(crate) mod io;
 mod monitor;
 #[cfg(feature = "profiler")]
 pub(crate) mod profiler;
+mod replay;
+mod rv32im;
+mod strace;
 #[cfg(test)]
 mod tests;
-
-use std::{cell::RefCell, fmt::Debug, io::Write, mem::take, rc::Rc};
+mod trace;
+mod vfs;
+
+use std::{
+    array,
+    collections::{BTreeSet, VecDeque},
+    fmt::Debug,
+    fs,
+    io::Write,
+    mem::take,
+    ops::Range,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
 use anyhow::{anyhow, bail, Context, Result};
 use crypto_bigint::{CheckedMul, Encoding, NonZero, U256, U512};
@@ -36,26 +57,47 @@ use risc0_zkp::{
         hash::sha::{BLOCK_BYTES, BLOCK_WORDS},
         log2_ceil,
     },
-    ZK_CYCLES,
+    MIN_CYCLES_PO2, ZK_CYCLES,
 };
 use risc0_zkvm_platform::{
     fileno,
     memory::MEM_SIZE,
     syscall::{
         bigint, ecall, halt,
-        reg_abi::{REG_A0, REG_A1, REG_A2, REG_A3, REG_A4, REG_T0},
+        nr::{SYS_LOG, SYS_RANDOM, SYS_WRITE},
+        reg_abi::{REG_A0, REG_A1, REG_A2, REG_A3, REG_A4, REG_A5, REG_T0},
     },
     PAGE_SIZE, WORD_SIZE,
 };
+#[cfg(test)]
 use rrs_lib::{instruction_executor::InstructionExecutor, HartState};
 use serde::{Deserialize, Serialize};
 
-pub use self::env::{ExecutorEnv, ExecutorEnvBuilder};
-use self::monitor::MemoryMonitor;
+pub use self::{
+    backtrace::{Backtrace, BacktraceFrame},
+    env::{
+        CancellationToken, ExecutorEnv, ExecutorEnvBuilder, Handlers, LocalHandlers, SendHandlers,
+    },
+    replay::SyscallDivergence,
+    strace::{RegionRead, SyscallTraceEntry},
+    trace::{diff_traces, TraceDivergence, TraceRecorder, TraceStep},
+    vfs::MountSource,
+};
+use self::{
+    io::{
+        syscalls::{GuestPanic, RandomState},
+        SyscallContext,
+    },
+    monitor::MemoryMonitor,
+    replay,
+    rv32im::{check_region, Fault},
+    strace::TracingContext,
+};
 use crate::{
     align_up,
     opcode::{MajorType, OpCode},
     receipt::ExitCode,
+    session::{GuestOutput, OutputStream, RandomSource, SegmentStats, SessionStats},
     Loader, MemoryImage, Program, Segment, SegmentRef, Session, SimpleSegmentRef,
 };
 
@@ -65,24 +107,47 @@ const SHA_CYCLES: usize = 72;
 /// Number of cycles required to complete a BigInt operation.
 const BIGINT_CYCLES: usize = 9;
 
+/// Number of instructions executed between checks for cancellation and the
+/// time limit.
+const INTERRUPT_CHECK_INTERVAL: usize = 1024;
+
 /// The Executor provides an implementation for the execution phase.
 ///
 /// The proving phase uses an execution trace generated by the Executor.
-pub struct Executor<'a> {
-    env: ExecutorEnv<'a>,
+pub struct Executor<'a, H: Handlers<'a> = LocalHandlers> {
+    env: ExecutorEnv<'a, H>,
     pre_image: MemoryImage,
     monitor: MemoryMonitor,
     pc: u32,
+    segment_pc: u32,
     init_cycles: usize,
     body_cycles: usize,
     segment_cycle: usize,
     segments: Vec<Box<dyn SegmentRef>>,
     insn_counter: u32,
     split_insn: Option<u32>,
+    // Whether the guest has consumed randomness in the current segment.
+    segment_random: bool,
     const_cycles: usize,
     pending_syscall: Option<SyscallRecord>,
+    // Output captured by the pending syscall, recorded once it commits.
+    pending_output: Option<(OutputStream, Vec<u8>)>,
+    output: Vec<GuestOutput>,
     syscalls: Vec<SyscallRecord>,
+    syscall_queue: Option<VecDeque<SyscallRecord>>,
     exit_code: Option<ExitCode>,
+    journal: Option<Journal>,
+    breakpoints: BTreeSet<u32>,
+    skip_breakpoint: bool,
+    stats: SessionStats,
+    pages_read: BTreeSet<u32>,
+    pages_written: BTreeSet<u32>,
+    #[cfg(feature = "backtrace")]
+    symbols: Option<backtrace::Symbols>,
+    // Step with the original rrs-based interpreter, used to cross-check
+    // [rv32im::execute].
+    #[cfg(test)]
+    legacy_step: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -91,11 +156,44 @@ pub struct SyscallRecord {
     pub regs: (u32, u32),
 }
 
+/// A snapshot of an [Executor] taken at a segment boundary.
+///
+/// The checkpoint holds the memory image (which includes the registers and the
+/// program counter) along with the last [ExitCode], which is everything needed
+/// to continue execution with [Executor::from_checkpoint], possibly in another
+/// process. Host-side state such as syscall handlers and file descriptors is
+/// not part of the checkpoint; it is supplied again through a new
+/// [ExecutorEnv].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExecutorCheckpoint {
+    pre_image: MemoryImage,
+    pc: u32,
+    exit_code: Option<ExitCode>,
+    // The state of the seeded random number generator, if one was used.
+    random: Option<RandomState>,
+}
+
+impl ExecutorCheckpoint {
+    /// Save this checkpoint to a file at `path`.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = bincode::serialize(self)?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Load a checkpoint previously written by [ExecutorCheckpoint::save].
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read(path)?;
+        Ok(bincode::deserialize(&contents)?)
+    }
+}
+
 #[derive(Clone)]
 struct OpCodeResult {
     pc: u32,
     exit_code: Option<ExitCode>,
     extra_cycles: usize,
+    ecall: Option<EcallKind>,
 }
 
 impl OpCodeResult {
@@ -104,6 +202,37 @@ impl OpCodeResult {
             pc,
             exit_code,
             extra_cycles,
+            ecall: None,
+        }
+    }
+
+    fn with_ecall(self, ecall: EcallKind) -> Self {
+        Self {
+            ecall: Some(ecall),
+            ..self
+        }
+    }
+}
+
+// The kind of ecall made by an instruction, which is traced and whose cycles
+// are reported in [SessionStats].
+#[derive(Clone)]
+enum EcallKind {
+    Halt,
+    Input,
+    Sha,
+    Bigint,
+    Syscall(String),
+}
+
+impl EcallKind {
+    fn number(&self) -> u32 {
+        match self {
+            EcallKind::Halt => ecall::HALT,
+            EcallKind::Input => ecall::INPUT,
+            EcallKind::Sha => ecall::SHA,
+            EcallKind::Bigint => ecall::BIGINT,
+            EcallKind::Syscall(_) => ecall::SOFTWARE,
         }
     }
 }
@@ -111,20 +240,20 @@ impl OpCodeResult {
 // Capture the journal output in a buffer that we can access afterwards.
 #[derive(Clone, Default)]
 struct Journal {
-    buf: Rc<RefCell<Vec<u8>>>,
+    buf: Arc<Mutex<Vec<u8>>>,
 }
 
 impl Write for Journal {
     fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
-        self.buf.borrow_mut().write(bytes)
+        self.buf.lock().unwrap().write(bytes)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.buf.borrow_mut().flush()
+        self.buf.lock().unwrap().flush()
     }
 }
 
-impl<'a> Executor<'a> {
+impl<'a, H: Handlers<'a>> Executor<'a, H> {
     /// Construct a new [Executor] from a [MemoryImage] and entry point.
     ///
     /// Before a guest program is proven, the [Executor] is responsible for
@@ -132,7 +261,7 @@ impl<'a> Executor<'a> {
     /// work will be done in each segment. This is the execution phase:
     /// the guest program is executed to determine how its proof should be
     /// divided into subparts.
-    pub fn new(env: ExecutorEnv<'a>, image: MemoryImage, pc: u32) -> Self {
+    pub fn new(env: ExecutorEnv<'a, H>, image: MemoryImage, pc: u32) -> Self {
         let pre_image = image.clone();
         let monitor = MemoryMonitor::new(image, env.trace_callback.is_some());
         let loader = Loader::new();
@@ -144,16 +273,31 @@ impl<'a> Executor<'a> {
             pre_image,
             monitor,
             pc,
+            segment_pc: pc,
             init_cycles,
             body_cycles: 0,
             segment_cycle: init_cycles,
             segments: Vec::new(),
             insn_counter: 0,
             split_insn: None,
+            segment_random: false,
             const_cycles,
             pending_syscall: None,
+            pending_output: None,
+            output: Vec::new(),
             syscalls: Vec::new(),
+            syscall_queue: None,
             exit_code: None,
+            journal: None,
+            breakpoints: BTreeSet::new(),
+            skip_breakpoint: false,
+            stats: SessionStats::default(),
+            pages_read: BTreeSet::new(),
+            pages_written: BTreeSet::new(),
+            #[cfg(feature = "backtrace")]
+            symbols: None,
+            #[cfg(test)]
+            legacy_step: false,
         }
     }
 
@@ -172,10 +316,56 @@ impl<'a> Executor<'a> {
     ///     .unwrap();
     /// let mut exec = Executor::from_elf(env, BENCH_ELF).unwrap();
     /// ```
-    pub fn from_elf(env: ExecutorEnv<'a>, elf: &[u8]) -> Result<Self> {
+    pub fn from_elf(env: ExecutorEnv<'a, H>, elf: &[u8]) -> Result<Self> {
         let program = Program::load_elf(&elf, MEM_SIZE as u32)?;
         let image = MemoryImage::new(&program, PAGE_SIZE as u32)?;
-        Ok(Self::new(env, image, program.entry))
+        #[cfg(feature = "backtrace")]
+        let symbols = match env.backtrace {
+            true => Some(backtrace::Symbols::new(elf)?),
+            false => None,
+        };
+        let mut exec = Self::new(env, image, program.entry);
+        #[cfg(feature = "backtrace")]
+        {
+            exec.symbols = symbols;
+        }
+        Ok(exec)
+    }
+
+    /// Construct a new [Executor] that resumes execution from an
+    /// [ExecutorCheckpoint].
+    ///
+    /// The `env` takes the place of the [ExecutorEnv] that was used when the
+    /// checkpoint was taken. If that one was seeded with
+    /// [crate::ExecutorEnvBuilder::rng_seed], the random numbers continue from
+    /// where the checkpoint left off, unless `env` has a custom `SYS_RANDOM`
+    /// handler.
+    pub fn from_checkpoint(mut env: ExecutorEnv<'a, H>, checkpoint: ExecutorCheckpoint) -> Self {
+        if let Some(random) = &checkpoint.random {
+            env.restore_random(random);
+        }
+        let mut exec = Self::new(env, checkpoint.pre_image, checkpoint.pc);
+        exec.exit_code = checkpoint.exit_code;
+        exec
+    }
+
+    /// Capture the state of this [Executor] as an [ExecutorCheckpoint].
+    ///
+    /// Checkpoints can only be taken at a segment boundary, e.g. after
+    /// [Executor::run] has returned with [ExitCode::Paused].
+    pub fn checkpoint(&self) -> Result<ExecutorCheckpoint> {
+        if self.body_cycles != 0 || self.pending_syscall.is_some() || !self.syscalls.is_empty() {
+            bail!("cannot checkpoint an executor in the middle of a segment");
+        }
+        if self.monitor.execute_only {
+            bail!("cannot checkpoint an executor in execute-only mode");
+        }
+        Ok(ExecutorCheckpoint {
+            pre_image: self.pre_image.clone(),
+            pc: self.pc,
+            exit_code: self.exit_code,
+            random: self.env.get_random_state(),
+        })
     }
 
     /// Run the executor until [ExitCode::Paused] or [ExitCode::Halted] is
@@ -199,79 +389,411 @@ impl<'a> Executor<'a> {
 
     /// Run the executor until [ExitCode::Paused] or [ExitCode::Halted] is
     /// reached, producing a [Session] as a result.
+    ///
+    /// Breakpoints and watchpoints are ignored; use
+    /// [Executor::run_until_stop_with_callback] to stop on them.
     pub fn run_with_callback<F>(&mut self, mut callback: F) -> Result<Session>
     where
         F: FnMut(Segment) -> Result<Box<dyn SegmentRef>>,
     {
+        // Stopping at a breakpoint or watchpoint doesn't end the call, so the
+        // time limit applies to all of the stops together.
+        let deadline = self.deadline();
+        loop {
+            if let StopReason::Exited(session) =
+                self.run_until_stop_inner(&mut callback, deadline)?
+            {
+                return Ok(session);
+            }
+        }
+    }
+
+    /// Run the executor until a breakpoint or watchpoint is hit, or until
+    /// [ExitCode::Paused] or [ExitCode::Halted] is reached.
+    ///
+    /// After stopping at a breakpoint or watchpoint, calling this again
+    /// resumes execution where it left off.
+    pub fn run_until_stop(&mut self) -> Result<StopReason> {
+        self.run_until_stop_with_callback(|segment| Ok(Box::new(SimpleSegmentRef::new(segment))))
+    }
+
+    /// Run the executor until a breakpoint or watchpoint is hit, or until
+    /// [ExitCode::Paused] or [ExitCode::Halted] is reached.
+    pub fn run_until_stop_with_callback<F>(&mut self, mut callback: F) -> Result<StopReason>
+    where
+        F: FnMut(Segment) -> Result<Box<dyn SegmentRef>>,
+    {
+        let deadline = self.deadline();
+        self.run_until_stop_inner(&mut callback, deadline)
+    }
+
+    fn run_until_stop_inner<F>(
+        &mut self,
+        callback: &mut F,
+        deadline: Option<Instant>,
+    ) -> Result<StopReason>
+    where
+        F: FnMut(Segment) -> Result<Box<dyn SegmentRef>>,
+    {
+        if self.monitor.execute_only {
+            bail!("cannot build segments for an execution started with Executor::execute_only");
+        }
+        self.start_run()?;
+        let mut count = 0;
+        loop {
+            if count % INTERRUPT_CHECK_INTERVAL == 0 {
+                self.check_interrupt(deadline)?;
+            }
+            count += 1;
+
+            if !self.skip_breakpoint && self.breakpoints.contains(&self.pc) {
+                // Don't stop at the same breakpoint again when resuming.
+                self.skip_breakpoint = true;
+                return Ok(StopReason::Breakpoint { pc: self.pc });
+            }
+
+            let pc = self.pc;
+            if let Some(exit_code) = self.step()? {
+                if let Some(exit_code) = self.finish_segment(exit_code, callback)? {
+                    return Ok(StopReason::Exited(self.finish_run(exit_code)));
+                }
+                // The instruction will be executed in the next segment.
+                continue;
+            }
+
+            self.skip_breakpoint = false;
+            if let Some(hit) = self.monitor.watch_hit.take() {
+                return Ok(StopReason::Watchpoint {
+                    pc,
+                    addr: hit.addr,
+                    access: hit.access,
+                });
+            }
+        }
+    }
+
+    /// Run the executor until [ExitCode::Paused] or [ExitCode::Halted] is
+    /// reached, without building any [Segment]s.
+    ///
+    /// This skips the bookkeeping needed for proving, such as tracking page
+    /// faults and hashing memory images, so it is considerably faster than
+    /// [Executor::run] when only the journal and exit code are needed.
+    /// Breakpoints and watchpoints are ignored.
+    ///
+    /// Once an [Executor] has been run in this mode, it can be resumed with
+    /// this method but can no longer produce a [Session]. Reaching the session
+    /// limit is reported with [ExitCode::SessionLimit] rather than as an
+    /// error.
+    pub fn execute_only(&mut self) -> Result<ExecutionSummary> {
+        if !self.monitor.execute_only && self.journal.is_some() {
+            bail!("cannot switch to execute-only mode in the middle of a run");
+        }
+        self.start_run()?;
+        self.monitor.execute_only = true;
+        let deadline = self.deadline();
+        let mut count = 0;
+        loop {
+            if count % INTERRUPT_CHECK_INTERVAL == 0 {
+                self.check_interrupt(deadline)?;
+            }
+            count += 1;
+
+            if let Some(exit_code) = self.step()? {
+                return Ok(self.finish_execute_only(exit_code));
+            }
+        }
+    }
+
+    /// Stop execution before the instruction at `pc` is executed.
+    pub fn add_breakpoint(&mut self, pc: u32) {
+        self.breakpoints.insert(pc);
+    }
+
+    /// Remove a breakpoint previously added with [Executor::add_breakpoint].
+    pub fn remove_breakpoint(&mut self, pc: u32) {
+        self.breakpoints.remove(&pc);
+    }
+
+    /// Stop execution after an instruction accesses memory within `range`.
+    ///
+    /// Read watchpoints trigger on guest load instructions. Write watchpoints
+    /// trigger on any write to guest memory, including the results of
+    /// syscalls and accelerators.
+    pub fn add_watchpoint(&mut self, range: Range<u32>, access: WatchAccess) {
+        self.monitor.watchpoints.push((range, access));
+    }
+
+    /// Remove all watchpoints covering exactly `range`.
+    pub fn remove_watchpoint(&mut self, range: Range<u32>) {
+        self.monitor
+            .watchpoints
+            .retain(|(watched, _)| *watched != range);
+    }
+
+    /// Returns the program counter of the next instruction to execute.
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    /// Returns the value of the register `idx`.
+    pub fn load_register(&self, idx: usize) -> u32 {
+        self.monitor.load_register(idx)
+    }
+
+    /// Read `len` bytes of guest memory starting at `addr`.
+    ///
+    /// This has no effect on execution; in particular, no pages are loaded.
+    pub fn read_memory(&self, addr: u32, len: u32) -> Result<Vec<u8>> {
+        match addr.checked_add(len) {
+            Some(end) if end as usize <= MEM_SIZE => {
+                Ok((addr..end).map(|addr| self.monitor.peek_u8(addr)).collect())
+            }
+            _ => bail!("Memory read out of range: 0x{addr:08x}, len: {len}"),
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.env
+            .time_limit
+            .and_then(|limit| Instant::now().checked_add(limit))
+    }
+
+    // Fail if the run was cancelled or is past its deadline. This is only
+    // called between instructions, so execution can be resumed afterwards.
+    fn check_interrupt(&self, deadline: Option<Instant>) -> Result<()> {
+        if let Some(token) = &self.env.cancellation_token {
+            if token.is_cancelled() {
+                return Err(ExecutorError::Cancelled.into());
+            }
+        }
+        if let Some(deadline) = deadline {
+            if Instant::now() >= deadline {
+                return Err(ExecutorError::TimeLimit.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn start_run(&mut self) -> Result<()> {
+        if self.journal.is_some() {
+            return Ok(());
+        }
+
         if let Some(ExitCode::Halted(_)) = self.exit_code {
             bail!("cannot resume an execution which exited with ExitCode::Halted");
         }
 
-        self.monitor.clear_session();
+        // In execute-only mode, guest memory is only held by the monitor.
+        if !self.monitor.execute_only {
+            self.monitor.clear_session();
+        }
 
         let journal = Journal::default();
         self.env
             .io
-            .borrow_mut()
+            .lock()
+            .unwrap()
             .with_write_fd(fileno::JOURNAL, journal.clone());
+        self.journal = Some(journal);
+        Ok(())
+    }
 
-        let mut run_loop = || -> Result<ExitCode> {
-            loop {
-                if let Some(exit_code) = self.step()? {
-                    let total_cycles = self.total_cycles();
-                    log::debug!("exit_code: {exit_code:?}, total_cycles: {total_cycles}");
-                    assert!(total_cycles <= (1 << self.env.segment_limit_po2));
-                    let pre_image = self.pre_image.clone();
-                    let post_image = self.monitor.build_image(self.pc);
-                    let post_image_id = post_image.compute_id();
-                    let syscalls = take(&mut self.syscalls);
-                    let faults = take(&mut self.monitor.faults);
-                    let segment = Segment::new(
-                        pre_image,
-                        post_image_id,
-                        faults,
-                        syscalls,
-                        exit_code,
-                        self.split_insn,
-                        log2_ceil(total_cycles.next_power_of_two()),
-                        self.segments
-                            .len()
-                            .try_into()
-                            .context("Too many segments to fit in u32")?,
-                        self.body_cycles,
-                    );
-                    let segment_ref = callback(segment)?;
-                    self.segments.push(segment_ref);
-                    match exit_code {
-                        ExitCode::SystemSplit => self.split(post_image),
-                        ExitCode::SessionLimit => bail!("Session limit exceeded"),
-                        ExitCode::Paused(inner) => {
-                            log::debug!("Paused({inner}): {}", self.segment_cycle);
-                            self.split(post_image);
-                            return Ok(exit_code);
-                        }
-                        ExitCode::Halted(inner) => {
-                            log::debug!("Halted({inner}): {}", self.segment_cycle);
-                            return Ok(exit_code);
-                        }
-                    };
-                };
+    // Close the current segment once [Executor::step] has returned an exit
+    // code. Returns the exit code of the run if execution should stop here.
+    fn finish_segment<F>(
+        &mut self,
+        exit_code: ExitCode,
+        callback: &mut F,
+    ) -> Result<Option<ExitCode>>
+    where
+        F: FnMut(Segment) -> Result<Box<dyn SegmentRef>>,
+    {
+        let total_cycles = self.total_cycles();
+        log::debug!("exit_code: {exit_code:?}, total_cycles: {total_cycles}");
+        assert!(total_cycles <= (1 << self.env.segment_limit_po2));
+        let pre_image = self.pre_image.clone();
+        let post_image = self.monitor.build_image(self.pc);
+        let post_image_id = post_image.compute_id();
+        let syscalls = take(&mut self.syscalls);
+        let faults = take(&mut self.monitor.faults);
+        let po2 = log2_ceil(total_cycles.next_power_of_two());
+        self.pages_read.extend(faults.reads.iter());
+        self.pages_written.extend(faults.writes.iter());
+        let segment = Segment::new(
+            pre_image,
+            post_image_id,
+            faults,
+            syscalls,
+            exit_code,
+            self.split_insn,
+            po2,
+            self.segments
+                .len()
+                .try_into()
+                .context("Too many segments to fit in u32")?,
+            self.body_cycles,
+            self.segment_random.then(|| self.random_source()),
+        );
+        let segments = match self.resplit_final_segment(&segment, total_cycles)? {
+            Some((segments, stats, output)) => {
+                self.stats.page_read_cycles += stats.page_read_cycles;
+                self.stats.page_write_cycles += stats.page_write_cycles;
+                self.stats.segments.extend(stats.segments);
+                // The re-execution writes the same output as the final
+                // segment did, so take the segment each write landed in from it.
+                let first = self.output.len() - output.len();
+                for (output, resplit) in self.output[first..].iter_mut().zip(output) {
+                    output.segment += resplit.segment;
+                }
+                segments
+            }
+            None => {
+                self.stats.page_read_cycles += self.monitor.page_read_cycles;
+                self.stats.page_write_cycles += self.monitor.page_write_cycles;
+                self.stats.segments.push(SegmentStats {
+                    po2,
+                    cycles: total_cycles,
+                    padding_cycles: (1 << po2) - total_cycles,
+                });
+                vec![segment]
             }
         };
+        for mut segment in segments {
+            segment.index = self
+                .segments
+                .len()
+                .try_into()
+                .context("Too many segments to fit in u32")?;
+            self.trace(TraceEvent::SegmentEnd {
+                index: segment.index,
+                exit_code: segment.exit_code,
+            });
+            let segment_ref = callback(segment)?;
+            self.segments.push(segment_ref);
+        }
+        match exit_code {
+            ExitCode::SystemSplit => {
+                self.split(post_image);
+                Ok(None)
+            }
+            ExitCode::SessionLimit => {
+                log::debug!("SessionLimit: {}", self.segment_cycle);
+                self.split(post_image);
+                let session = self.finish_run(exit_code);
+                Err(ExecutorError::SessionLimit(Box::new(session)).into())
+            }
+            ExitCode::Paused(inner) => {
+                log::debug!("Paused({inner}): {}", self.segment_cycle);
+                self.split(post_image);
+                Ok(Some(exit_code))
+            }
+            ExitCode::Halted(inner) => {
+                log::debug!("Halted({inner}): {}", self.segment_cycle);
+                Ok(Some(exit_code))
+            }
+        }
+    }
 
-        let exit_code = run_loop()?;
+    // With an automatic segment limit, the final segment of a run is usually
+    // mostly padding. When splitting it in two is estimated to be cheaper to
+    // prove, re-execute it from its pre-image with half the segment limit,
+    // replaying its syscalls, and return the resulting segments if they are
+    // in fact cheaper, along with the output the re-execution captured.
+    fn resplit_final_segment(
+        &self,
+        segment: &Segment,
+        total_cycles: usize,
+    ) -> Result<Option<(Vec<Segment>, SessionStats, Vec<GuestOutput>)>> {
+        if !self.env.segment_limit_auto
+            || !matches!(segment.exit_code, ExitCode::Halted(_) | ExitCode::Paused(_))
+            || segment.po2 <= MIN_CYCLES_PO2
+        {
+            return Ok(None);
+        }
+
+        // Every segment pays for the loader and for paging in its working set,
+        // so estimate the size of the second half from those costs as
+        // observed in this segment.
+        let overhead =
+            self.const_cycles + self.monitor.page_read_cycles + self.monitor.page_write_cycles;
+        let limit_po2 = segment.po2 - 1;
+        let first = 1 << limit_po2;
+        let second = (total_cycles + overhead)
+            .saturating_sub(first)
+            .next_power_of_two()
+            .max(1 << MIN_CYCLES_PO2);
+        if first + second >= 1 << segment.po2 {
+            return Ok(None);
+        }
+
+        log::debug!(
+            "resplit: po2: {} into {} + {second} cycles",
+            segment.po2,
+            first
+        );
+        let mut env = ExecutorEnv::<LocalHandlers>::default();
+        env.segment_limit_po2 = limit_po2;
+        env.segment_limit_auto = true;
+        env.capture_output = self.env.capture_output;
+        let mut exec = Executor::new(env, segment.pre_image.clone(), self.segment_pc);
+        exec.syscall_queue = Some(segment.syscalls.iter().cloned().collect());
+        let mut segments = Vec::new();
+        let session = exec
+            .run_with_callback(|segment| {
+                segments.push(segment.clone());
+                Ok(Box::new(SimpleSegmentRef::new(segment)))
+            })
+            .context("Failed to re-execute the final segment")?;
+
+        if session.stats.total_cycles() >= 1 << segment.po2 {
+            log::debug!("resplit: no improvement");
+            return Ok(None);
+        }
+        // The re-execution replays the syscalls of this executor, so its
+        // randomness came from the same source.
+        for segment in segments.iter_mut() {
+            segment.random = segment.random.map(|_| self.random_source());
+        }
+        Ok(Some((segments, session.stats, session.output)))
+    }
+
+    fn finish_run(&mut self, exit_code: ExitCode) -> Session {
         self.exit_code = Some(exit_code);
-        Ok(Session::new(
+        let journal = self.journal.take().unwrap_or_default();
+        let mut session = Session::new(
             take(&mut self.segments),
-            journal.buf.take(),
+            take(&mut *journal.buf.lock().unwrap()),
             exit_code,
-        ))
+        );
+        session.stats = take(&mut self.stats);
+        session.output = take(&mut self.output);
+        session.stats.pages_read = take(&mut self.pages_read).len();
+        session.stats.pages_written = take(&mut self.pages_written).len();
+        session
+    }
+
+    fn finish_execute_only(&mut self, exit_code: ExitCode) -> ExecutionSummary {
+        self.exit_code = Some(exit_code);
+        let journal = self.journal.take().unwrap_or_default();
+        let stats = take(&mut self.stats);
+        // Start the session limit afresh if execution is resumed.
+        self.body_cycles = 0;
+        self.insn_counter = 0;
+        self.segment_cycle = self.init_cycles;
+        ExecutionSummary {
+            journal: take(&mut *journal.buf.lock().unwrap()),
+            exit_code,
+            cycles: stats.insn_cycles,
+            output: take(&mut self.output),
+        }
     }
 
     fn split(&mut self, pre_image: MemoryImage) {
         self.pre_image = pre_image;
+        self.segment_pc = self.pc;
         self.body_cycles = 0;
         self.split_insn = None;
+        self.segment_random = false;
         self.insn_counter = 0;
         self.segment_cycle = self.init_cycles;
         self.monitor.clear_segment();
@@ -281,18 +803,38 @@ impl<'a> Executor<'a> {
     ///
     /// This can be directly used by debuggers.
     pub fn step(&mut self) -> Result<Option<ExitCode>> {
+        self.monitor.watch_hit = None;
+
         if let Some(limit) = self.env.get_session_limit() {
             if self.session_cycle() >= limit {
                 return Ok(Some(ExitCode::SessionLimit));
             }
         }
 
-        let insn = self.monitor.load_u32(self.pc);
-        let opcode = OpCode::decode(insn, self.pc)?;
+        let decoded = self
+            .monitor
+            .fetch(self.pc)
+            .map_err(|fault| self.fault_error(fault))?;
+        let opcode = decoded.opcode;
 
         let op_result = if opcode.major == MajorType::ECall {
             self.ecall()?
         } else {
+            #[cfg(test)]
+            if self.legacy_step {
+                return self.step_legacy(opcode);
+            }
+            let pc = rv32im::execute(&mut self.monitor, self.pc, decoded.insn)
+                .map_err(|fault| self.fault_error(fault))?;
+            OpCodeResult::new(pc, None, 0)
+        };
+
+        self.finish_step(opcode, op_result)
+    }
+
+    #[cfg(test)]
+    fn step_legacy(&mut self, opcode: OpCode) -> Result<Option<ExitCode>> {
+        let op_result = {
             let registers = self.monitor.load_registers();
             let mut hart = HartState {
                 registers,
@@ -314,6 +856,10 @@ impl<'a> Executor<'a> {
             OpCodeResult::new(hart.pc, None, 0)
         };
 
+        self.finish_step(opcode, op_result)
+    }
+
+    fn finish_step(&mut self, opcode: OpCode, op_result: OpCodeResult) -> Result<Option<ExitCode>> {
         // try to execute the next instruction
         // if the segment limit is exceeded:
         // * don't increment the PC
@@ -329,7 +875,7 @@ impl<'a> Executor<'a> {
         //     total_pending_cycles,
         //     self.total_cycles()
         // );
-        let exit_code = if total_pending_cycles > segment_limit {
+        let exit_code = if !self.monitor.execute_only && total_pending_cycles > segment_limit {
             self.split_insn = Some(self.insn_counter);
             log::debug!("split: [{}] pc: 0x{:08x}", self.segment_cycle, self.pc,);
             self.monitor.undo();
@@ -350,30 +896,120 @@ impl<'a> Executor<'a> {
         );
 
         if let Some(ref trace_callback) = self.env.trace_callback {
-            trace_callback.borrow_mut()(TraceEvent::InstructionStart {
+            let mut trace_callback = trace_callback.lock().unwrap();
+            trace_callback(TraceEvent::InstructionStart {
                 cycle: self.session_cycle() as u32,
                 pc: self.pc,
             })
             .unwrap();
 
+            if let Some(ref ecall) = op_result.ecall {
+                let syscall = match ecall {
+                    EcallKind::Syscall(name) => Some(name.clone()),
+                    _ => None,
+                };
+                trace_callback(TraceEvent::EcallStart {
+                    ecall: ecall.number(),
+                    syscall,
+                })
+                .unwrap();
+            }
+
             for event in self.monitor.trace_events.iter() {
-                trace_callback.borrow_mut()(event.clone()).unwrap();
+                trace_callback(event.clone()).unwrap();
+            }
+
+            if let Some(ref ecall) = op_result.ecall {
+                trace_callback(TraceEvent::EcallEnd {
+                    ecall: ecall.number(),
+                })
+                .unwrap();
+            }
+
+            let (accel, syscall) = match op_result.ecall {
+                Some(EcallKind::Sha | EcallKind::Bigint) => (op_result.extra_cycles, 0),
+                Some(EcallKind::Syscall(_)) => (0, op_result.extra_cycles),
+                Some(EcallKind::Halt | EcallKind::Input) | None => (0, 0),
+            };
+            trace_callback(TraceEvent::InstructionCycles {
+                pc: self.pc,
+                insn: opcode.cycles as u32,
+                paging: self.monitor.pending_page_cycles() as u32,
+                accel: accel as u32,
+                syscall: syscall as u32,
+            })
+            .unwrap();
+        }
+
+        if let Some((stream, data)) = self.pending_output.take() {
+            self.record_output(stream, data);
+        }
+
+        let cycles = opcode.cycles + op_result.extra_cycles;
+        match op_result.ecall {
+            Some(EcallKind::Sha) => self.stats.sha_cycles += op_result.extra_cycles,
+            Some(EcallKind::Bigint) => self.stats.bigint_cycles += op_result.extra_cycles,
+            Some(EcallKind::Syscall(name)) => {
+                if name == SYS_RANDOM.as_str() {
+                    self.segment_random = true;
+                }
+                let syscall = self.stats.syscalls.entry(name).or_default();
+                syscall.calls += 1;
+                syscall.cycles += cycles;
             }
+            Some(EcallKind::Halt | EcallKind::Input) | None => (),
         }
+        self.stats.insn_cycles += cycles;
 
         self.pc = op_result.pc;
         self.insn_counter += 1;
-        self.body_cycles += opcode.cycles + op_result.extra_cycles;
+        self.body_cycles += cycles;
         let page_read_cycles = self.monitor.page_read_cycles;
         // log::debug!("page_read_cycles: {page_read_cycles}");
         self.segment_cycle = self.init_cycles + page_read_cycles + self.body_cycles;
         self.monitor.commit(self.session_cycle());
         if let Some(syscall) = self.pending_syscall.take() {
-            self.syscalls.push(syscall);
+            if !self.monitor.execute_only {
+                self.syscalls.push(syscall);
+            }
         }
         op_result.exit_code
     }
 
+    fn random_source(&self) -> RandomSource {
+        if self.env.syscall_replayer.is_some() {
+            RandomSource::Replayed
+        } else {
+            self.env.random_source
+        }
+    }
+
+    fn record_output(&mut self, stream: OutputStream, data: Vec<u8>) {
+        let output = GuestOutput {
+            stream,
+            cycle: self.session_cycle(),
+            segment: self.segments.len() as u32,
+            data,
+        };
+        // Output is logged when it's first written, not again when the final
+        // segment is re-executed.
+        if let Some(level) = self
+            .env
+            .get_output_level(stream)
+            .filter(|_| self.syscall_queue.is_none())
+        {
+            log::log!(target: "risc0_zkvm::guest", level, "{output}");
+        }
+        self.output.push(output);
+    }
+
+    // Send an event which isn't tied to a single instruction.
+    fn trace(&self, event: TraceEvent) {
+        if let Some(ref trace_callback) = self.env.trace_callback {
+            trace_callback.lock().unwrap()(event).unwrap();
+        }
+    }
+
     fn total_cycles(&self) -> usize {
         self.const_cycles
             + self.monitor.page_read_cycles
@@ -385,6 +1021,60 @@ impl<'a> Executor<'a> {
         self.segments.len() * self.env.get_segment_limit() + self.segment_cycle
     }
 
+    // The position in the execution of the current instruction, for errors.
+    fn fault_context(&self) -> FaultContext {
+        FaultContext {
+            pc: self.pc,
+            cycle: self.session_cycle(),
+            segment_index: self.segments.len() as u32,
+            backtrace: self.backtrace(),
+        }
+    }
+
+    #[cfg(feature = "backtrace")]
+    fn backtrace(&self) -> Option<Backtrace> {
+        let symbols = self.symbols.as_ref()?;
+        let regs = array::from_fn(|idx| self.monitor.load_register(idx));
+        let read_u32 = |addr: u32| {
+            if addr % WORD_SIZE as u32 != 0 || addr as usize + WORD_SIZE > MEM_SIZE {
+                return None;
+            }
+            let bytes = array::from_fn(|idx| self.monitor.peek_u8(addr + idx as u32));
+            Some(u32::from_le_bytes(bytes))
+        };
+        Some(symbols.backtrace(self.pc, regs, read_u32))
+    }
+
+    #[cfg(not(feature = "backtrace"))]
+    fn backtrace(&self) -> Option<Backtrace> {
+        None
+    }
+
+    fn fault_error(&self, fault: Fault) -> anyhow::Error {
+        let context = self.fault_context();
+        match fault {
+            Fault::InvalidInstruction(insn) => ExecutorError::InvalidInstruction { insn, context },
+            Fault::MisalignedAccess(addr) => ExecutorError::MisalignedAccess { addr, context },
+            Fault::OutOfBounds(addr) => ExecutorError::OutOfBounds { addr, context },
+        }
+        .into()
+    }
+
+    // Fail if the guest passed an ecall a pointer to `len` bytes which it
+    // couldn't access itself.
+    fn check_ecall_region(&self, addr: u32, len: usize) -> Result<()> {
+        check_region(addr, len).map_err(|fault| self.fault_error(fault))
+    }
+
+    fn invalid_ecall_args(&self, ecall: u32, reason: String) -> anyhow::Error {
+        ExecutorError::InvalidEcallArgs {
+            ecall,
+            reason,
+            context: self.fault_context(),
+        }
+        .into()
+    }
+
     fn ecall(&mut self) -> Result<OpCodeResult> {
         match self.monitor.load_register(REG_T0) {
             ecall::HALT => self.ecall_halt(),
@@ -392,7 +1082,11 @@ impl<'a> Executor<'a> {
             ecall::SOFTWARE => self.ecall_software(),
             ecall::SHA => self.ecall_sha(),
             ecall::BIGINT => self.ecall_bigint(),
-            ecall => bail!("Unknown ecall {ecall:?}"),
+            ecall => Err(ExecutorError::UnknownEcall {
+                ecall,
+                context: self.fault_context(),
+            }
+            .into()),
         }
     }
 
@@ -401,30 +1095,34 @@ impl<'a> Executor<'a> {
         let output_ptr = self.monitor.load_register(REG_A1);
         let halt_type = tot_reg & 0xff;
         let user_exit = (tot_reg >> 8) & 0xff;
+        self.check_ecall_region(output_ptr, DIGEST_BYTES)?;
         self.monitor
             .load_array::<{ DIGEST_WORDS * WORD_SIZE }>(output_ptr);
 
         match halt_type {
-            halt::TERMINATE => Ok(OpCodeResult::new(
-                self.pc,
-                Some(ExitCode::Halted(user_exit)),
-                0,
-            )),
+            halt::TERMINATE => Ok(
+                OpCodeResult::new(self.pc, Some(ExitCode::Halted(user_exit)), 0)
+                    .with_ecall(EcallKind::Halt),
+            ),
             halt::PAUSE => Ok(OpCodeResult::new(
                 self.pc + WORD_SIZE as u32,
                 Some(ExitCode::Paused(user_exit)),
                 0,
-            )),
-            _ => bail!("Illegal halt type: {halt_type}"),
+            )
+            .with_ecall(EcallKind::Halt)),
+            _ => {
+                Err(self.invalid_ecall_args(ecall::HALT, format!("Illegal halt type: {halt_type}")))
+            }
         }
     }
 
     fn ecall_input(&mut self) -> Result<OpCodeResult> {
         log::debug!("ecall(input)");
         let in_addr = self.monitor.load_register(REG_A0);
+        self.check_ecall_region(in_addr, DIGEST_BYTES)?;
         self.monitor
             .load_array::<{ DIGEST_WORDS * WORD_SIZE }>(in_addr);
-        Ok(OpCodeResult::new(self.pc + WORD_SIZE as u32, None, 0))
+        Ok(OpCodeResult::new(self.pc + WORD_SIZE as u32, None, 0).with_ecall(EcallKind::Input))
     }
 
     fn ecall_sha(&mut self) -> Result<OpCodeResult> {
@@ -433,6 +1131,8 @@ impl<'a> Executor<'a> {
         let mut block1_ptr = self.monitor.load_register(REG_A2);
         let mut block2_ptr = self.monitor.load_register(REG_A3);
         let count = self.monitor.load_register(REG_A4);
+        self.check_ecall_region(out_state_ptr, DIGEST_BYTES)?;
+        self.check_ecall_region(in_state_ptr, DIGEST_BYTES)?;
 
         let in_state: [u8; DIGEST_BYTES] = self.monitor.load_array(in_state_ptr);
         let mut state: [u32; DIGEST_WORDS] = bytemuck::cast_slice(&in_state).try_into().unwrap();
@@ -442,13 +1142,18 @@ impl<'a> Executor<'a> {
 
         log::debug!("Initial sha state: {state:08x?}");
         for _ in 0..count {
+            self.check_ecall_region(block1_ptr, DIGEST_BYTES)?;
+            self.check_ecall_region(block2_ptr, DIGEST_BYTES)?;
             let mut block = [0u32; BLOCK_WORDS];
             for i in 0..DIGEST_WORDS {
-                block[i] = self.monitor.load_u32(block1_ptr + (i * WORD_SIZE) as u32);
+                block[i] = self
+                    .monitor
+                    .load_u32_traced(block1_ptr + (i * WORD_SIZE) as u32);
             }
             for i in 0..DIGEST_WORDS {
-                block[DIGEST_WORDS + i] =
-                    self.monitor.load_u32(block2_ptr + (i * WORD_SIZE) as u32);
+                block[DIGEST_WORDS + i] = self
+                    .monitor
+                    .load_u32_traced(block2_ptr + (i * WORD_SIZE) as u32);
             }
             log::debug!("Compressing block {block:02x?}");
             sha2::compress256(
@@ -474,7 +1179,8 @@ impl<'a> Executor<'a> {
             self.pc + WORD_SIZE as u32,
             None,
             SHA_CYCLES * count as usize,
-        ))
+        )
+        .with_ecall(EcallKind::Sha))
     }
 
     // Computes the state transitions for the BIGINT ecall.
@@ -487,18 +1193,24 @@ impl<'a> Executor<'a> {
         let y_ptr = self.monitor.load_register(REG_A3);
         let n_ptr = self.monitor.load_register(REG_A4);
 
+        if op != 0 {
+            return Err(self.invalid_ecall_args(ecall::BIGINT, format!("Illegal bigint op: {op}")));
+        }
+        for ptr in [z_ptr, x_ptr, y_ptr, n_ptr] {
+            self.check_ecall_region(ptr, bigint::WIDTH_BYTES)?;
+        }
+
         let mut load_bigint_le_bytes = |ptr: u32| -> [u8; bigint::WIDTH_BYTES] {
             let mut arr = [0u32; bigint::WIDTH_WORDS];
             for i in 0..bigint::WIDTH_WORDS {
-                arr[i] = self.monitor.load_u32(ptr + (i * WORD_SIZE) as u32).to_le();
+                arr[i] = self
+                    .monitor
+                    .load_u32_traced(ptr + (i * WORD_SIZE) as u32)
+                    .to_le();
             }
             bytemuck::cast(arr)
         };
 
-        if op != 0 {
-            anyhow::bail!("ecall_bigint preflight: op must be set to 0");
-        }
-
         // Load inputs.
         let x = U256::from_le_bytes(load_bigint_le_bytes(x_ptr));
         let y = U256::from_le_bytes(load_bigint_le_bytes(y_ptr));
@@ -523,11 +1235,10 @@ impl<'a> Executor<'a> {
                 .store_u32(z_ptr + (i * WORD_SIZE) as u32, word.to_le());
         }
 
-        Ok(OpCodeResult::new(
-            self.pc + WORD_SIZE as u32,
-            None,
-            BIGINT_CYCLES,
-        ))
+        Ok(
+            OpCodeResult::new(self.pc + WORD_SIZE as u32, None, BIGINT_CYCLES)
+                .with_ecall(EcallKind::Bigint),
+        )
     }
 
     fn ecall_software(&mut self) -> Result<OpCodeResult> {
@@ -543,24 +1254,15 @@ impl<'a> Executor<'a> {
             log::debug!("Replay syscall: {syscall:?}");
             syscall
         } else {
-            let mut to_guest = vec![0; to_guest_words as usize];
-            let handler = self
-                .env
-                .get_syscall(&syscall_name)
-                .ok_or(anyhow!("Unknown syscall: {syscall_name:?}"))?;
-            let (a0, a1) =
-                handler
-                    .borrow_mut()
-                    .syscall(&syscall_name, &mut self.monitor, &mut to_guest)?;
-            let syscall = SyscallRecord {
-                to_guest,
-                regs: (a0, a1),
-            };
+            let syscall = self.dispatch_syscall(&syscall_name, to_guest_words)?;
             self.pending_syscall = Some(syscall.clone());
             syscall
         };
 
         let (a0, a1) = syscall.regs;
+        if to_guest_ptr as usize + syscall.to_guest.len() * WORD_SIZE > MEM_SIZE {
+            return Err(self.fault_error(Fault::OutOfBounds(to_guest_ptr)));
+        }
         self.monitor
             .store_region(to_guest_ptr, bytemuck::cast_slice(&syscall.to_guest));
         self.monitor.store_register(REG_A0, a0);
@@ -570,11 +1272,416 @@ impl<'a> Executor<'a> {
 
         // One cycle for the ecall cycle, then one for each chunk or
         // portion thereof then one to save output (a0, a1)
-        Ok(OpCodeResult::new(
-            self.pc + WORD_SIZE as u32,
-            None,
-            1 + chunks + 1,
-        ))
+        Ok(
+            OpCodeResult::new(self.pc + WORD_SIZE as u32, None, 1 + chunks + 1)
+                .with_ecall(EcallKind::Syscall(syscall_name)),
+        )
+    }
+
+    // Produces the result of a syscall, either by calling its handler or by
+    // taking it from the syscall log when replaying.
+    fn dispatch_syscall(&mut self, name: &str, to_guest_words: u32) -> Result<SyscallRecord> {
+        if let Some(queue) = &mut self.syscall_queue {
+            let syscall = queue
+                .pop_front()
+                .ok_or(anyhow!("Syscall {name:?} is missing from the segment"))?;
+            if self.env.capture_output {
+                self.pending_output = guest_output(name, &mut self.monitor);
+            }
+            return Ok(syscall);
+        }
+
+        let cycle = self.session_cycle();
+        let mut reads = Vec::new();
+        let syscall = if let Some(replayer) = self.env.syscall_replayer.clone() {
+            if replay::is_passthrough(name) {
+                self.call_syscall_handler(name, to_guest_words, &mut reads)?;
+            }
+            replayer.lock().unwrap().next(name, to_guest_words)?
+        } else {
+            let syscall = self.call_syscall_handler(name, to_guest_words, &mut reads)?;
+            if let Some(ref recorder) = self.env.syscall_recorder {
+                recorder
+                    .lock()
+                    .unwrap()
+                    .record(name, to_guest_words, &syscall)?;
+            }
+            syscall
+        };
+
+        if let Some(ref tracer) = self.env.syscall_tracer {
+            tracer.lock().unwrap().trace(&SyscallTraceEntry {
+                name: name.to_string(),
+                cycle,
+                pc: self.pc,
+                to_guest_words,
+                regs: syscall.regs,
+                reads,
+            })?;
+        }
+        Ok(syscall)
+    }
+
+    // Call the registered handler for a syscall. When tracing, the regions of
+    // memory it reads are appended to `reads`.
+    fn call_syscall_handler(
+        &mut self,
+        name: &str,
+        to_guest_words: u32,
+        reads: &mut Vec<RegionRead>,
+    ) -> Result<SyscallRecord> {
+        let mut to_guest = vec![0; to_guest_words as usize];
+        let Some(handler) = self.env.get_syscall(name) else {
+            return Err(ExecutorError::UnknownSyscall {
+                name: name.to_string(),
+                context: self.fault_context(),
+            }
+            .into());
+        };
+        let mut handler = handler.lock().unwrap();
+        let mut tracing_ctx;
+        let ctx: &mut dyn SyscallContext = if self.env.syscall_tracer.is_some() {
+            tracing_ctx = TracingContext {
+                inner: &mut self.monitor,
+                reads,
+            };
+            &mut tracing_ctx
+        } else {
+            &mut self.monitor
+        };
+        // Captured output bypasses the handler, so that it doesn't also reach
+        // the console of the host.
+        if self.env.capture_output {
+            if let Some(output) = guest_output(name, ctx) {
+                self.pending_output = Some(output);
+                return Ok(SyscallRecord {
+                    to_guest,
+                    regs: (0, 0),
+                });
+            }
+        }
+        let result = handler.syscall(name, ctx, &mut to_guest);
+        let (a0, a1) = result.map_err(|err| match err.downcast::<GuestPanic>() {
+            Ok(GuestPanic(message)) => ExecutorError::GuestPanic {
+                message,
+                context: self.fault_context(),
+            }
+            .into(),
+            Err(err) => err,
+        })?;
+        Ok(SyscallRecord {
+            to_guest,
+            regs: (a0, a1),
+        })
+    }
+}
+
+// The stream and contents of the output written by a syscall, if it is one
+// which writes to the log, stdout or stderr.
+fn guest_output(name: &str, ctx: &mut dyn SyscallContext) -> Option<(OutputStream, Vec<u8>)> {
+    let (stream, buf_ptr, buf_len) = if name == SYS_LOG.as_str() {
+        (OutputStream::Log, REG_A3, REG_A4)
+    } else if name == SYS_WRITE.as_str() {
+        match ctx.load_register(REG_A3) {
+            fileno::STDOUT => (OutputStream::Stdout, REG_A4, REG_A5),
+            fileno::STDERR => (OutputStream::Stderr, REG_A4, REG_A5),
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+    let buf_ptr = ctx.load_register(buf_ptr);
+    let buf_len = ctx.load_register(buf_len);
+    Some((stream, ctx.load_region(buf_ptr, buf_len)))
+}
+
+/// The position in the execution at which an [ExecutorError] occurred.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FaultContext {
+    /// The program counter of the instruction that caused the error.
+    pub pc: u32,
+
+    /// The session cycle at which the error occurred.
+    pub cycle: usize,
+
+    /// The index of the [Segment] in which the error occurred.
+    pub segment_index: u32,
+
+    /// The guest stack at the point of the error, if backtraces were enabled
+    /// in the [ExecutorEnv].
+    pub backtrace: Option<Backtrace>,
+}
+
+impl core::fmt::Display for FaultContext {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "pc: 0x{:08x}, cycle: {}, segment: {}",
+            self.pc, self.cycle, self.segment_index
+        )
+    }
+}
+
+/// Errors which stop an [Executor] before the guest exits.
+///
+/// These are returned wrapped in an [anyhow::Error], and can be recovered with
+/// [anyhow::Error::downcast] or [anyhow::Error::downcast_ref].
+pub enum ExecutorError {
+    /// The [CancellationToken] given to the [ExecutorEnv] was cancelled.
+    ///
+    /// Execution can be resumed after resetting the token.
+    Cancelled,
+
+    /// The time limit given to the [ExecutorEnv] was reached.
+    ///
+    /// Execution can be resumed, with a fresh time limit, by running the
+    /// executor again.
+    TimeLimit,
+
+    /// The session limit given to the [ExecutorEnv] was reached.
+    ///
+    /// This holds the [Session] produced so far, whose final [Segment] has an
+    /// [ExitCode] of [SessionLimit](ExitCode::SessionLimit). Running the
+    /// executor again continues from where it stopped, as a new session with
+    /// a fresh session limit.
+    SessionLimit(Box<Session>),
+
+    /// The guest panicked with the given message.
+    GuestPanic {
+        /// The panic message
+        message: String,
+        /// Where the guest called the panic syscall
+        context: FaultContext,
+    },
+
+    /// The guest tried to execute a word which is not a valid instruction.
+    InvalidInstruction {
+        /// The invalid instruction
+        insn: u32,
+        /// Where the instruction was fetched from
+        context: FaultContext,
+    },
+
+    /// The guest accessed memory at an address that is not aligned to the
+    /// size of the access, or jumped to an address that is not word aligned.
+    MisalignedAccess {
+        /// The misaligned address
+        addr: u32,
+        /// Where the access occurred
+        context: FaultContext,
+    },
+
+    /// The guest accessed memory outside of the guest address space.
+    OutOfBounds {
+        /// The address of the access
+        addr: u32,
+        /// Where the access occurred
+        context: FaultContext,
+    },
+
+    /// The guest called a syscall which has no handler in the [ExecutorEnv].
+    UnknownSyscall {
+        /// The name of the syscall
+        name: String,
+        /// Where the guest called the syscall
+        context: FaultContext,
+    },
+
+    /// The guest issued an `ecall` of an unknown kind.
+    UnknownEcall {
+        /// The value of register t0, which selects the kind of ecall
+        ecall: u32,
+        /// Where the guest issued the ecall
+        context: FaultContext,
+    },
+
+    /// The guest issued an `ecall` with arguments that it doesn't accept,
+    /// other than pointers to memory the guest can't access.
+    InvalidEcallArgs {
+        /// The value of register t0, which selects the kind of ecall
+        ecall: u32,
+        /// What was wrong with the arguments
+        reason: String,
+        /// Where the guest issued the ecall
+        context: FaultContext,
+    },
+}
+
+impl Debug for ExecutorError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ExecutorError::Cancelled => write!(f, "Cancelled"),
+            ExecutorError::TimeLimit => write!(f, "TimeLimit"),
+            ExecutorError::SessionLimit(session) => {
+                write!(f, "SessionLimit({} segments)", session.segments.len())
+            }
+            ExecutorError::GuestPanic { message, context } => f
+                .debug_struct("GuestPanic")
+                .field("message", message)
+                .field("context", context)
+                .finish(),
+            ExecutorError::InvalidInstruction { insn, context } => f
+                .debug_struct("InvalidInstruction")
+                .field("insn", insn)
+                .field("context", context)
+                .finish(),
+            ExecutorError::MisalignedAccess { addr, context } => f
+                .debug_struct("MisalignedAccess")
+                .field("addr", addr)
+                .field("context", context)
+                .finish(),
+            ExecutorError::OutOfBounds { addr, context } => f
+                .debug_struct("OutOfBounds")
+                .field("addr", addr)
+                .field("context", context)
+                .finish(),
+            ExecutorError::UnknownSyscall { name, context } => f
+                .debug_struct("UnknownSyscall")
+                .field("name", name)
+                .field("context", context)
+                .finish(),
+            ExecutorError::UnknownEcall { ecall, context } => f
+                .debug_struct("UnknownEcall")
+                .field("ecall", ecall)
+                .field("context", context)
+                .finish(),
+            ExecutorError::InvalidEcallArgs {
+                ecall,
+                reason,
+                context,
+            } => f
+                .debug_struct("InvalidEcallArgs")
+                .field("ecall", ecall)
+                .field("reason", reason)
+                .field("context", context)
+                .finish(),
+        }
+    }
+}
+
+impl core::fmt::Display for ExecutorError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ExecutorError::Cancelled => write!(f, "Execution cancelled"),
+            ExecutorError::TimeLimit => write!(f, "Execution time limit exceeded"),
+            ExecutorError::SessionLimit(_) => write!(f, "Session limit exceeded"),
+            ExecutorError::GuestPanic { message, context } => {
+                write!(f, "Guest panicked: {message} ({context})")
+            }
+            ExecutorError::InvalidInstruction { insn, context } => {
+                write!(f, "Invalid instruction 0x{insn:08x} ({context})")
+            }
+            ExecutorError::MisalignedAccess { addr, context } => {
+                write!(f, "Misaligned memory access at 0x{addr:08x} ({context})")
+            }
+            ExecutorError::OutOfBounds { addr, context } => {
+                write!(f, "Out of bounds memory access at 0x{addr:08x} ({context})")
+            }
+            ExecutorError::UnknownSyscall { name, context } => {
+                write!(f, "Unknown syscall: {name:?} ({context})")
+            }
+            ExecutorError::UnknownEcall { ecall, context } => {
+                write!(f, "Unknown ecall {ecall} ({context})")
+            }
+            ExecutorError::InvalidEcallArgs {
+                ecall,
+                reason,
+                context,
+            } => {
+                write!(
+                    f,
+                    "Invalid arguments to ecall {ecall}: {reason} ({context})"
+                )
+            }
+        }?;
+        if let Some(backtrace) = self
+            .context()
+            .and_then(|context| context.backtrace.as_ref())
+        {
+            write!(f, "\nguest backtrace:{backtrace}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ExecutorError {}
+
+impl ExecutorError {
+    /// The position in the execution at which the error occurred, for errors
+    /// caused by the guest.
+    pub fn context(&self) -> Option<&FaultContext> {
+        match self {
+            ExecutorError::Cancelled
+            | ExecutorError::TimeLimit
+            | ExecutorError::SessionLimit(_) => None,
+            ExecutorError::GuestPanic { context, .. }
+            | ExecutorError::InvalidInstruction { context, .. }
+            | ExecutorError::MisalignedAccess { context, .. }
+            | ExecutorError::OutOfBounds { context, .. }
+            | ExecutorError::UnknownSyscall { context, .. }
+            | ExecutorError::UnknownEcall { context, .. }
+            | ExecutorError::InvalidEcallArgs { context, .. } => Some(context),
+        }
+    }
+}
+
+/// The result of running an [Executor] with [Executor::execute_only].
+#[derive(Clone, Debug)]
+pub struct ExecutionSummary {
+    /// The data publicly committed by the guest program.
+    pub journal: Vec<u8>,
+
+    /// The [ExitCode] of the execution.
+    pub exit_code: ExitCode,
+
+    /// The number of cycles used to execute instructions, including the
+    /// cycles spent in accelerators and syscalls.
+    ///
+    /// This does not include the cost of paging memory in and out, which
+    /// depends on how the execution would be split into [Segment]s.
+    pub cycles: usize,
+
+    /// The output of the guest, if captured with
+    /// [ExecutorEnvBuilder::capture_output].
+    pub output: Vec<GuestOutput>,
+}
+
+/// The reason why [Executor::run_until_stop] returned control to the host.
+pub enum StopReason {
+    /// Execution stopped before the instruction at a breakpoint.
+    Breakpoint {
+        /// Program counter of the breakpoint
+        pc: u32,
+    },
+
+    /// Execution stopped after an instruction accessed memory being watched.
+    Watchpoint {
+        /// Program counter of the instruction that accessed memory
+        pc: u32,
+        /// Address that was accessed
+        addr: u32,
+        /// Kind of access that triggered the watchpoint
+        access: WatchAccess,
+    },
+
+    /// The guest reached [ExitCode::Paused] or [ExitCode::Halted].
+    Exited(Session),
+}
+
+/// The kind of memory access that triggers a watchpoint.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WatchAccess {
+    /// Trigger when memory is read
+    Read,
+    /// Trigger when memory is written
+    Write,
+    /// Trigger when memory is read or written
+    ReadWrite,
+}
+
+impl WatchAccess {
+    fn matches(&self, access: WatchAccess) -> bool {
+        *self == WatchAccess::ReadWrite || *self == access
     }
 }
 
@@ -604,6 +1711,66 @@ pub enum TraceEvent {
         /// Value of word that's been written
         value: u32,
     },
+
+    /// A memory location has been read by a load instruction
+    MemoryRead {
+        /// Address that's been read
+        addr: u32,
+        /// Value that's been read, zero extended
+        value: u32,
+    },
+
+    /// A page has been paged in to the current segment
+    PageIn {
+        /// Index of the page
+        page_idx: u32,
+    },
+
+    /// A page has been written for the first time in the current segment,
+    /// and so will be paged out at its end
+    PageOut {
+        /// Index of the page
+        page_idx: u32,
+    },
+
+    /// An ecall has been made; the events it caused follow, up to the
+    /// matching [TraceEvent::EcallEnd]
+    EcallStart {
+        /// The type of ecall, as in [risc0_zkvm_platform::syscall::ecall]
+        ecall: u32,
+        /// The name of the syscall, for software ecalls
+        syscall: Option<String>,
+    },
+
+    /// An ecall has completed
+    EcallEnd {
+        /// The type of ecall, as in [risc0_zkvm_platform::syscall::ecall]
+        ecall: u32,
+    },
+
+    /// A segment has ended, because it was split, or the guest paused or
+    /// halted
+    SegmentEnd {
+        /// Index of the segment
+        index: u32,
+        /// How the segment ended
+        exit_code: ExitCode,
+    },
+
+    /// The cycles spent on an instruction, sent after all its other events
+    InstructionCycles {
+        /// Program counter of the instruction
+        pc: u32,
+        /// Cycles spent executing the instruction itself
+        insn: u32,
+        /// Cycles spent paging in and out the memory accessed by the
+        /// instruction
+        paging: u32,
+        /// Cycles spent in the SHA-256 and BigInt accelerators
+        accel: u32,
+        /// Cycles spent transferring syscall data to the guest
+        syscall: u32,
+    },
 }
 
 impl Debug for TraceEvent {
@@ -614,6 +1781,29 @@ impl Debug for TraceEvent {
             }
             Self::RegisterSet { reg, value } => write!(f, "RegisterSet({reg}, 0x{value:08X})"),
             Self::MemorySet { addr, value } => write!(f, "MemorySet(0x{addr:08X}, 0x{value:08X})"),
+            Self::MemoryRead { addr, value } => {
+                write!(f, "MemoryRead(0x{addr:08X}, 0x{value:08X})")
+            }
+            Self::PageIn { page_idx } => write!(f, "PageIn(0x{page_idx:08X})"),
+            Self::PageOut { page_idx } => write!(f, "PageOut(0x{page_idx:08X})"),
+            Self::EcallStart { ecall, syscall } => match syscall {
+                Some(name) => write!(f, "EcallStart({ecall}, {name})"),
+                None => write!(f, "EcallStart({ecall})"),
+            },
+            Self::EcallEnd { ecall } => write!(f, "EcallEnd({ecall})"),
+            Self::SegmentEnd { index, exit_code } => {
+                write!(f, "SegmentEnd({index}, {exit_code:?})")
+            }
+            Self::InstructionCycles {
+                pc,
+                insn,
+                paging,
+                accel,
+                syscall,
+            } => write!(
+                f,
+                "InstructionCycles(0x{pc:08X}, {insn}, {paging}, {accel}, {syscall})"
+            ),
         }
     }
 }
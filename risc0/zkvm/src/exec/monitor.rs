@@ -12,19 +12,30 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{array, collections::BTreeSet, mem::take};
+use std::{array, mem::take, ops::Range};
 
 use anyhow::Result;
 use risc0_zkp::core::hash::sha::BLOCK_BYTES;
-use risc0_zkvm_platform::{memory::SYSTEM, syscall::reg_abi::REG_MAX, PAGE_SIZE, WORD_SIZE};
+use risc0_zkvm_platform::{
+    memory::{MEM_SIZE, SYSTEM},
+    syscall::reg_abi::REG_MAX,
+    PAGE_SIZE, WORD_SIZE,
+};
 use rrs_lib::{MemAccessSize, Memory};
 
-use super::{io::SyscallContext, TraceEvent};
+use super::{
+    io::SyscallContext,
+    rv32im::{Decoded, Fault},
+    TraceEvent, WatchAccess,
+};
 use crate::{session::PageFaults, MemoryImage};
 
 /// The number of blocks that fit within a single page.
 const BLOCKS_PER_PAGE: usize = PAGE_SIZE / BLOCK_BYTES;
 
+/// The number of instructions that fit within a single page.
+const WORDS_PER_PAGE: usize = PAGE_SIZE / WORD_SIZE;
+
 const SHA_INIT: usize = 5;
 const SHA_LOAD: usize = 16;
 const SHA_MAIN: usize = 52;
@@ -43,6 +54,11 @@ enum Action {
     StoreReg(usize, u32),
 }
 
+pub struct WatchHit {
+    pub addr: u32,
+    pub access: WatchAccess,
+}
+
 #[derive(Clone)]
 struct Page {
     buf: Vec<u8>,
@@ -64,7 +80,8 @@ pub struct MemoryMonitor {
     image: MemoryImage,
     pub faults: PageFaults,
     session_cycle: usize,
-    pub trace_events: BTreeSet<TraceEvent>,
+    // In the order they happened during the current instruction.
+    pub trace_events: Vec<TraceEvent>,
     resident: Vec<bool>,
     dirty: Vec<bool>,
     pending_actions: Vec<Action>,
@@ -72,7 +89,14 @@ pub struct MemoryMonitor {
     pub page_write_cycles: usize,
     enable_trace: bool,
     pages: Vec<Option<Page>>,
+    // Instructions decoded from each page, indexed by word offset.
+    decoded: Vec<Option<Box<[Option<Decoded>]>>>,
     registers: [u32; REG_MAX],
+    pub watchpoints: Vec<(Range<u32>, WatchAccess)>,
+    pub watch_hit: Option<WatchHit>,
+    // Skip the page and undo bookkeeping that is only needed to build
+    // segments.
+    pub execute_only: bool,
 }
 
 impl MemoryMonitor {
@@ -81,11 +105,12 @@ impl MemoryMonitor {
         let resident = vec![false; num_pages];
         let dirty = vec![false; num_pages];
         let pages = vec![None; num_pages];
+        let decoded = vec![None; num_pages];
         Self {
             image,
             faults: PageFaults::default(),
             session_cycle: 0,
-            trace_events: BTreeSet::new(),
+            trace_events: Vec::new(),
             resident,
             dirty,
             pending_actions: Vec::new(),
@@ -93,7 +118,11 @@ impl MemoryMonitor {
             page_write_cycles: 0,
             enable_trace,
             pages,
+            decoded,
             registers: [0; REG_MAX],
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            execute_only: false,
         }
     }
 
@@ -120,10 +149,66 @@ impl MemoryMonitor {
         u32::from_le_bytes(bytes)
     }
 
+    /// Fetch the instruction at `pc`, decoding the basic block that starts
+    /// there if it hasn't been seen since the page was last written.
+    pub fn fetch(&mut self, pc: u32) -> Result<Decoded, Fault> {
+        if pc % WORD_SIZE as u32 != 0 {
+            return Err(Fault::MisalignedAccess(pc));
+        }
+        if pc as usize >= MEM_SIZE {
+            return Err(Fault::OutOfBounds(pc));
+        }
+        self.load_page(pc);
+        let info = &self.image.info;
+        let page_idx = info.get_page_index(pc) as usize;
+        let offset = (pc % info.page_size) as usize / WORD_SIZE;
+        if let Some(decoded) = self.decoded[page_idx]
+            .as_ref()
+            .and_then(|page| page[offset])
+        {
+            return Ok(decoded);
+        }
+        self.decode_block(pc)
+    }
+
+    fn decode_block(&mut self, pc: u32) -> Result<Decoded, Fault> {
+        let info = &self.image.info;
+        let page_idx = info.get_page_index(pc);
+        let start = (pc % info.page_size) as usize / WORD_SIZE;
+        let page = self.pages[page_idx as usize].get_or_insert_with(|| Page {
+            buf: self.image.load_page(page_idx),
+        });
+        let decoded = self.decoded[page_idx as usize]
+            .get_or_insert_with(|| vec![None; WORDS_PER_PAGE].into_boxed_slice());
+
+        let mut first = None;
+        for offset in start..WORDS_PER_PAGE {
+            let insn_pc = pc + ((offset - start) * WORD_SIZE) as u32;
+            let word = u32::from_le_bytes(
+                page.buf[offset * WORD_SIZE..(offset + 1) * WORD_SIZE]
+                    .try_into()
+                    .unwrap(),
+            );
+            let insn = match Decoded::new(word, insn_pc) {
+                Ok(insn) => insn,
+                // Only the instruction being fetched must be valid; anything
+                // after it may be data.
+                Err(err) if first.is_none() => return Err(err),
+                Err(_) => break,
+            };
+            decoded[offset] = Some(insn);
+            first.get_or_insert(insn);
+            if insn.insn.ends_block() {
+                break;
+            }
+        }
+        Ok(first.unwrap())
+    }
+
     fn load_page(&mut self, addr: u32) {
         let info = &self.image.info;
         let page_idx = info.get_page_index(addr);
-        if self.resident[page_idx as usize] {
+        if self.execute_only || self.resident[page_idx as usize] {
             return;
         }
 
@@ -138,6 +223,9 @@ impl MemoryMonitor {
 
         log::debug!("load_page: 0x{page_idx:08x}");
         self.resident[page_idx as usize] = true;
+        if self.enable_trace {
+            self.trace_events.push(TraceEvent::PageIn { page_idx });
+        }
         self.pending_actions
             .push(Action::PageRead(page_idx, page_cycles));
         self.page_read_cycles += page_cycles;
@@ -147,7 +235,7 @@ impl MemoryMonitor {
     fn mark_page(&mut self, addr: u32) {
         let info = &self.image.info;
         let page_idx = info.get_page_index(addr);
-        if self.dirty[page_idx as usize] {
+        if self.execute_only || self.dirty[page_idx as usize] {
             return;
         }
 
@@ -162,15 +250,50 @@ impl MemoryMonitor {
 
         log::debug!("mark_page: 0x{page_idx:08x}");
         self.dirty[page_idx as usize] = true;
+        if self.enable_trace {
+            self.trace_events.push(TraceEvent::PageOut { page_idx });
+        }
         self.pending_actions
             .push(Action::PageWrite(page_idx, page_cycles));
         self.page_write_cycles += page_cycles;
         self.faults.writes.insert(page_idx);
     }
 
+    /// Read a byte without paging it in or recording any activity.
+    pub fn peek_u8(&self, addr: u32) -> u8 {
+        let info = &self.image.info;
+        let page_idx = info.get_page_index(addr);
+        let offset = (addr % info.page_size) as usize;
+        match self.pages[page_idx as usize] {
+            Some(ref page) => page.buf[offset],
+            None => self.image.load_page(page_idx)[offset],
+        }
+    }
+
+    /// Load a word on behalf of an ecall, recording the read in the trace
+    /// like the loads made by instructions.
+    pub fn load_u32_traced(&mut self, addr: u32) -> u32 {
+        let value = self.load_u32(addr);
+        self.trace_read(addr, value);
+        value
+    }
+
+    fn load_u8_traced(&mut self, addr: u32) -> u8 {
+        let value = self.load_u8(addr);
+        self.trace_read(addr, value as u32);
+        value
+    }
+
+    fn trace_read(&mut self, addr: u32, value: u32) {
+        if self.enable_trace {
+            self.trace_events
+                .push(TraceEvent::MemoryRead { addr, value });
+        }
+    }
+
     pub fn load_array<const N: usize>(&mut self, addr: u32) -> [u8; N] {
         // log::trace!("load_array: 0x{addr:08x}");
-        array::from_fn(|idx| self.load_u8(addr + idx as u32))
+        array::from_fn(|idx| self.load_u8_traced(addr + idx as u32))
     }
 
     pub fn load_register(&self, idx: usize) -> u32 {
@@ -178,6 +301,7 @@ impl MemoryMonitor {
         self.registers[idx]
     }
 
+    #[cfg(test)]
     pub fn load_registers(&self) -> [u32; REG_MAX] {
         self.registers.clone()
     }
@@ -210,7 +334,7 @@ impl MemoryMonitor {
         // log::trace!("load_string: 0x{addr:08x}");
         let mut s: Vec<u8> = Vec::new();
         loop {
-            let bytes = self.load_u8(addr);
+            let bytes = self.load_u8_traced(addr);
             if bytes == 0 {
                 break;
             }
@@ -220,10 +344,26 @@ impl MemoryMonitor {
         String::from_utf8(s).map_err(anyhow::Error::msg)
     }
 
+    // Record the first access that matches a watchpoint.
+    fn check_watchpoints(&mut self, addr: u32, len: u32, access: WatchAccess) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        let hit = self.watchpoints.iter().any(|(range, kind)| {
+            kind.matches(access) && addr < range.end && range.start < addr + len
+        });
+        if hit {
+            self.watch_hit = Some(WatchHit { addr, access });
+        }
+    }
+
     fn raw_store_u8(&mut self, addr: u32, data: u8) {
         // log::trace!("raw_store_u8: 0x{addr:08x}");
-        let old = self.load_u8(addr);
-        self.pending_actions.push(Action::StoreU8(addr, old));
+        self.check_watchpoints(addr, 1, WatchAccess::Write);
+        if !self.execute_only {
+            let old = self.load_u8(addr);
+            self.pending_actions.push(Action::StoreU8(addr, old));
+        }
         self.store_bytes(addr, &[data]);
         self.mark_page(addr);
     }
@@ -232,7 +372,7 @@ impl MemoryMonitor {
         // log::trace!("store_u8: 0x{addr:08x}");
         self.raw_store_u8(addr, data);
         if self.enable_trace {
-            self.trace_events.insert(TraceEvent::MemorySet {
+            self.trace_events.push(TraceEvent::MemorySet {
                 addr,
                 value: data as u32,
             });
@@ -242,12 +382,15 @@ impl MemoryMonitor {
     pub fn store_u16(&mut self, addr: u32, data: u16) {
         // log::trace!("store_u16: 0x{addr:08x}");
         assert_eq!(addr % 2, 0, "unaligned store");
-        let old = self.load_u16(addr);
-        self.pending_actions.push(Action::StoreU16(addr, old));
+        self.check_watchpoints(addr, 2, WatchAccess::Write);
+        if !self.execute_only {
+            let old = self.load_u16(addr);
+            self.pending_actions.push(Action::StoreU16(addr, old));
+        }
         self.store_bytes(addr, &data.to_le_bytes());
         self.mark_page(addr);
         if self.enable_trace {
-            self.trace_events.insert(TraceEvent::MemorySet {
+            self.trace_events.push(TraceEvent::MemorySet {
                 addr,
                 value: data as u32,
             });
@@ -257,12 +400,15 @@ impl MemoryMonitor {
     pub fn store_u32(&mut self, addr: u32, data: u32) {
         // log::trace!("store_u32: 0x{addr:08x}");
         assert_eq!(addr % WORD_SIZE as u32, 0, "unaligned store");
-        let old = self.load_u32(addr);
-        self.pending_actions.push(Action::StoreU32(addr, old));
+        self.check_watchpoints(addr, WORD_SIZE as u32, WatchAccess::Write);
+        if !self.execute_only {
+            let old = self.load_u32(addr);
+            self.pending_actions.push(Action::StoreU32(addr, old));
+        }
         self.store_bytes(addr, &data.to_le_bytes());
         self.mark_page(addr);
         if self.enable_trace {
-            self.trace_events.insert(TraceEvent::MemorySet {
+            self.trace_events.push(TraceEvent::MemorySet {
                 addr,
                 value: data as u32,
             });
@@ -279,12 +425,14 @@ impl MemoryMonitor {
 
     pub fn store_register(&mut self, idx: usize, data: u32) {
         // log::trace!("store_register: x{idx}");
-        let old = self.load_register(idx);
-        self.pending_actions.push(Action::StoreReg(idx, old));
+        if !self.execute_only {
+            let old = self.load_register(idx);
+            self.pending_actions.push(Action::StoreReg(idx, old));
+        }
         self.registers[idx] = data;
         self.mark_page(SYSTEM.start() as u32);
         if self.enable_trace {
-            self.trace_events.insert(TraceEvent::RegisterSet {
+            self.trace_events.push(TraceEvent::RegisterSet {
                 reg: idx,
                 value: data,
             });
@@ -300,9 +448,13 @@ impl MemoryMonitor {
                 buf: self.image.load_page(page_idx),
             })
             .store_bytes(offset, bytes);
+        // Any instructions decoded from this page may now be stale.
+        self.decoded[page_idx as usize] = None;
     }
 
     pub fn undo(&mut self) {
+        // The step is discarded, along with everything it did.
+        self.trace_events.clear();
         let pending_actions = take(&mut self.pending_actions);
         for action in pending_actions.iter().rev() {
             match action {
@@ -338,6 +490,18 @@ impl MemoryMonitor {
         }
     }
 
+    /// The cycles needed to page in and out the pages first accessed since the
+    /// last commit.
+    pub fn pending_page_cycles(&self) -> usize {
+        self.pending_actions
+            .iter()
+            .map(|action| match action {
+                Action::PageRead(_, cycles) | Action::PageWrite(_, cycles) => *cycles,
+                _ => 0,
+            })
+            .sum()
+    }
+
     // commit all pending activity
     pub fn commit(&mut self, cycle: usize) {
         self.pending_actions.clear();
@@ -388,11 +552,25 @@ impl MemoryMonitor {
 
 impl Memory for MemoryMonitor {
     fn read_mem(&mut self, addr: u32, size: MemAccessSize) -> Option<u32> {
-        match size {
-            MemAccessSize::Byte => Some(self.load_u8(addr) as u32),
-            MemAccessSize::HalfWord => Some(self.load_u16(addr) as u32),
-            MemAccessSize::Word => Some(self.load_u32(addr)),
+        let value = match size {
+            MemAccessSize::Byte => {
+                self.check_watchpoints(addr, 1, WatchAccess::Read);
+                self.load_u8(addr) as u32
+            }
+            MemAccessSize::HalfWord => {
+                self.check_watchpoints(addr, 2, WatchAccess::Read);
+                self.load_u16(addr) as u32
+            }
+            MemAccessSize::Word => {
+                self.check_watchpoints(addr, WORD_SIZE as u32, WatchAccess::Read);
+                self.load_u32(addr)
+            }
+        };
+        if self.enable_trace {
+            self.trace_events
+                .push(TraceEvent::MemoryRead { addr, value });
         }
+        Some(value)
     }
 
     fn write_mem(&mut self, addr: u32, size: MemAccessSize, store_data: u32) -> bool {
@@ -415,11 +593,11 @@ impl SyscallContext for MemoryMonitor {
     }
 
     fn load_u32(&mut self, addr: u32) -> u32 {
-        MemoryMonitor::load_u32(self, addr)
+        self.load_u32_traced(addr)
     }
 
     fn load_u8(&mut self, addr: u32) -> u8 {
-        MemoryMonitor::load_u8(self, addr)
+        self.load_u8_traced(addr)
     }
 }
 
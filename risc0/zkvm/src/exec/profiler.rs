@@ -15,29 +15,32 @@
 //! Support for profiling the guest.
 //!
 //! This counts cycles spent at each location when executing the
-//! guest.  It does not trace full stack traces, but only provides the
-//! top level stack frame.  (More than one stack frame may show up
-//! in the case of inlined functions).
-
-// TODO:
-//
-//   * Count the full stack instead of the top frame; the "gimli"
-//   crate's UnwindSection and Evaluation should help us do this
-//
-//  * Demangle symbols
+//! guest, including the cycles spent paging memory in and out, in the
+//! accelerators, and transferring syscall data, each of which is
+//! reported as a separate sample type.  The pprof output only provides the top
+//! level stack frame. (More than one stack frame may show up in the case of
+//! inlined functions).
+//!
+//! The folded stack and flamegraph output attribute cycles to the full
+//! call stack, which is tracked by watching for calls and returns in
+//! the trace.
 
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+};
 
 use addr2line::{
     fallible_iterator::FallibleIterator,
     object::{read::File, Object, ObjectSegment},
     Context, LookupResult,
 };
-use anyhow::Result;
-use gimli::{EndianRcSlice, RunTimeEndian};
+use anyhow::{bail, Result};
+use gimli::{EndianArcSlice, RunTimeEndian};
 use prost::Message;
+use risc0_zkvm_platform::{syscall::reg_abi::REG_RA, WORD_SIZE};
 
-use super::TraceEvent;
+use super::{backtrace::load_context, TraceEvent};
 
 mod proto {
     // Generated proto interface.
@@ -49,13 +52,13 @@ pub struct Profiler {
     // Current program counter
     pc: u32,
 
-    // Cycle count when the last instruction started
-    cycle: u32,
-
     // Counts per program counter
-    counts: HashMap<u32, usize>,
+    counts: HashMap<u32, Cycles>,
+
+    // Counts per call stack
+    call_stack: CallStack,
 
-    ctx: Context<EndianRcSlice<RunTimeEndian>>,
+    ctx: Context<EndianArcSlice<RunTimeEndian>>,
 
     profile: ProfileBuilder,
 }
@@ -74,7 +77,7 @@ pub struct Frame {
     pub filename: String,
 }
 
-fn decode_frame(fr: addr2line::Frame<EndianRcSlice<RunTimeEndian>>) -> Option<Frame> {
+fn decode_frame(fr: addr2line::Frame<EndianArcSlice<RunTimeEndian>>) -> Option<Frame> {
     Some(Frame {
         name: fr.function.as_ref()?.raw_name().ok()?.to_string(),
         lineno: fr.location.as_ref()?.line? as i64,
@@ -82,7 +85,7 @@ fn decode_frame(fr: addr2line::Frame<EndianRcSlice<RunTimeEndian>>) -> Option<Fr
     })
 }
 
-fn lookup_pc(pc: u32, ctx: &Context<EndianRcSlice<RunTimeEndian>>) -> Vec<Frame> {
+fn lookup_pc(pc: u32, ctx: &Context<EndianArcSlice<RunTimeEndian>>) -> Vec<Frame> {
     let frames = match ctx.find_frames(pc as u64) {
         LookupResult::Output(result) => result.unwrap(),
         LookupResult::Load {
@@ -100,11 +103,11 @@ impl Profiler {
     /// Return a new profile from the given RISCV ELF.
     pub fn new(filename: &str, elf_data: &[u8]) -> Result<Self> {
         let file = File::parse(elf_data)?;
-        let ctx = Context::new(&file)?;
+        let ctx = load_context(&file)?;
         let mut profiler = Profiler {
             pc: u32::MAX,
-            cycle: 0,
             counts: HashMap::new(),
+            call_stack: CallStack::default(),
             ctx,
             profile: ProfileBuilder::new(),
         };
@@ -137,16 +140,35 @@ impl Profiler {
     /// passing to ProverOpts::with_trace_callback.
     pub fn make_trace_callback<'a>(
         &'a mut self,
-    ) -> impl FnMut(TraceEvent) -> anyhow::Result<()> + 'a {
+    ) -> impl FnMut(TraceEvent) -> anyhow::Result<()> + Send + 'a {
         |event| {
             match event {
-                TraceEvent::InstructionStart { cycle, pc } => {
-                    // Count against the last program counter.
-                    let cycles = cycle - self.cycle;
-                    let orig_pc = self.pc;
-                    *self.counts.entry(orig_pc).or_insert(0) += cycles as usize;
+                TraceEvent::InstructionStart { pc, .. } => {
+                    self.call_stack.step(pc);
                     self.pc = pc;
-                    self.cycle = cycle;
+                }
+                TraceEvent::InstructionCycles {
+                    pc,
+                    insn,
+                    paging,
+                    accel,
+                    syscall,
+                } => {
+                    let cycles = Cycles {
+                        insn: insn as usize,
+                        paging: paging as usize,
+                        accel: accel as usize,
+                        syscall: syscall as usize,
+                    };
+                    self.counts.entry(pc).or_default().add(&cycles);
+                    self.call_stack.add_cycles(cycles.total());
+                }
+                TraceEvent::RegisterSet { reg, value }
+                    if reg == REG_RA && value == self.pc.wrapping_add(WORD_SIZE as u32) =>
+                {
+                    // Saving the address of the next instruction as the
+                    // return address means this instruction is a call.
+                    self.call_stack.call_pending = true;
                 }
                 _ => (),
             }
@@ -160,7 +182,7 @@ impl Profiler {
             return;
         }
 
-        for (pc, count) in self.counts.iter() {
+        for (pc, cycles) in self.counts.iter() {
             let frames = lookup_pc(*pc, &self.ctx);
             let loc = proto::Location {
                 address: *pc as u64,
@@ -175,7 +197,13 @@ impl Profiler {
             };
             let sample = proto::Sample {
                 location_id: vec![self.profile.get_location(loc)],
-                value: vec![*count as i64],
+                value: vec![
+                    cycles.total() as i64,
+                    cycles.insn as i64,
+                    cycles.paging as i64,
+                    cycles.accel as i64,
+                    cycles.syscall as i64,
+                ],
                 ..Default::default()
             };
             self.profile.add_sample(sample);
@@ -196,6 +224,154 @@ impl Profiler {
     pub fn encode_to_vec(&mut self) -> Vec<u8> {
         self.as_protobuf().encode_to_vec()
     }
+
+    /// Write the cycles spent in each call stack in the folded stack format
+    /// used by Brendan Gregg's flamegraph tools, one stack per line.
+    pub fn write_folded(&self, mut writer: impl Write) -> Result<()> {
+        for (stack, count) in self.folded() {
+            writeln!(writer, "{stack} {count}")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Render the cycles spent in each call stack as an SVG flamegraph.
+    ///
+    /// Fails without writing anything if no cycles were profiled.
+    pub fn write_flamegraph(&self, writer: impl Write) -> Result<()> {
+        let lines: Vec<_> = self
+            .folded()
+            .into_iter()
+            .map(|(stack, count)| format!("{stack} {count}"))
+            .collect();
+        if lines.is_empty() {
+            bail!("No cycles were profiled");
+        }
+        let mut opts = inferno::flamegraph::Options::default();
+        opts.title = "Guest cycles".to_string();
+        opts.count_name = "cycles".to_string();
+        inferno::flamegraph::from_lines(&mut opts, lines.iter().map(String::as_str), writer)?;
+        Ok(())
+    }
+
+    // Symbolize the call stacks, with inlined functions resolved, keyed by
+    // the semicolon separated function names from outermost to innermost.
+    fn folded(&self) -> BTreeMap<String, usize> {
+        let mut names: HashMap<u32, Vec<String>> = HashMap::new();
+        let mut folded = BTreeMap::new();
+        for (stack, count) in self.call_stack.counts.iter() {
+            let frames: Vec<String> = stack
+                .iter()
+                .flat_map(|pc| {
+                    names
+                        .entry(*pc)
+                        .or_insert_with(|| function_names(*pc, &self.ctx))
+                        .clone()
+                })
+                .collect();
+            *folded.entry(frames.join(";")).or_insert(0) += count;
+        }
+        folded
+    }
+}
+
+// Returns the demangled names of the functions at `pc`, outermost first,
+// with inlined functions resolved.
+fn function_names(pc: u32, ctx: &Context<EndianArcSlice<RunTimeEndian>>) -> Vec<String> {
+    let mut names: Vec<String> = match ctx.find_frames(pc as u64).skip_all_loads() {
+        Ok(frames) => frames
+            .filter_map(|frame| {
+                Ok(frame
+                    .function
+                    .as_ref()
+                    .and_then(|function| function.demangle().ok())
+                    // Semicolons separate frames in the folded format.
+                    .map(|name| name.replace(';', ":")))
+            })
+            .collect()
+            .unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+    if names.is_empty() {
+        names.push(format!("0x{pc:08x}"));
+    }
+    names.reverse();
+    names
+}
+
+// The cycles spent at a location, by kind.
+#[derive(Clone, Copy, Default)]
+struct Cycles {
+    insn: usize,
+    paging: usize,
+    accel: usize,
+    syscall: usize,
+}
+
+impl Cycles {
+    fn add(&mut self, other: &Cycles) {
+        self.insn += other.insn;
+        self.paging += other.paging;
+        self.accel += other.accel;
+        self.syscall += other.syscall;
+    }
+
+    fn total(&self) -> usize {
+        self.insn + self.paging + self.accel + self.syscall
+    }
+}
+
+// Tracks the guest call stack using the trace: an instruction which saves the
+// address of the next instruction as the return address is a call, and a
+// jump to a saved return address is a return.
+#[derive(Default)]
+struct CallStack {
+    // Program counters of each call site, outermost first, followed by the
+    // current program counter.
+    frames: Vec<u32>,
+
+    // The return address of each call site in `frames`.
+    returns: Vec<u32>,
+
+    // Whether the current instruction is a call.
+    call_pending: bool,
+
+    // Counts per stack of program counters.
+    counts: HashMap<Vec<u32>, usize>,
+}
+
+impl CallStack {
+    fn add_cycles(&mut self, cycles: usize) {
+        if self.frames.is_empty() {
+            return;
+        }
+        match self.counts.get_mut(self.frames.as_slice()) {
+            Some(count) => *count += cycles,
+            None => {
+                self.counts.insert(self.frames.clone(), cycles);
+            }
+        }
+    }
+
+    fn step(&mut self, pc: u32) {
+        if self.call_pending {
+            self.call_pending = false;
+            if let Some(&site) = self.frames.last() {
+                self.returns.push(site.wrapping_add(WORD_SIZE as u32));
+                self.frames.push(pc);
+                return;
+            }
+        }
+        // A return to an outer frame unwinds every frame inside it.
+        if let Some(depth) = self.returns.iter().rposition(|&ra| ra == pc) {
+            self.returns.truncate(depth);
+            self.frames.truncate(depth + 1);
+        }
+        match self.frames.last_mut() {
+            Some(top) => *top = pc,
+            None => self.frames.push(pc),
+        }
+    }
 }
 
 struct ProfileBuilder {
@@ -217,13 +393,23 @@ impl ProfileBuilder {
         // First string must always be the empty string
         assert_eq!(0, builder.get_string(""));
 
-        // Set up defaults for us
-        let sample_type = proto::ValueType {
-            r#type: builder.get_string("cycles"),
-            unit: builder.get_string("count"),
-            ..Default::default()
-        };
-        builder.profile.sample_type.push(sample_type);
+        // Set up defaults for us; the total comes first so that it is shown
+        // by default, followed by the breakdown in the same order as
+        // [Cycles].
+        for name in [
+            "cycles",
+            "insn_cycles",
+            "paging_cycles",
+            "accel_cycles",
+            "syscall_cycles",
+        ] {
+            let sample_type = proto::ValueType {
+                r#type: builder.get_string(name),
+                unit: builder.get_string("count"),
+                ..Default::default()
+            };
+            builder.profile.sample_type.push(sample_type);
+        }
 
         builder
     }
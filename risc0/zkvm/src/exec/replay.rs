@@ -0,0 +1,150 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording and replaying the results of host syscalls.
+//!
+//! A syscall log is a stream of bincode-encoded entries, one for each syscall
+//! issued by the guest, in the order in which they were issued.
+
+use std::{
+    io::{ErrorKind, Write},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use risc0_zkvm_platform::syscall::{
+    nr::{SYS_LOG, SYS_PANIC, SYS_WRITE},
+    SyscallName,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{env::Handlers, SyscallRecord};
+
+/// Syscalls whose handlers still run while replaying.
+///
+/// These only consume data from the guest, so running them reproduces the
+/// host-side output (e.g. the journal) without depending on external state.
+const REPLAY_PASSTHROUGH: &[SyscallName] = &[SYS_LOG, SYS_PANIC, SYS_WRITE];
+
+/// Returns true if the handler of the named syscall still runs while
+/// replaying.
+pub(crate) fn is_passthrough(name: &str) -> bool {
+    REPLAY_PASSTHROUGH.iter().any(|x| x.as_str() == name)
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyscallLogEntry {
+    name: String,
+    to_guest_words: u32,
+    record: SyscallRecord,
+}
+
+/// Error returned when a replayed guest issues a syscall that differs from the
+/// one found in the syscall log.
+#[derive(Debug)]
+pub struct SyscallDivergence {
+    /// The position of the syscall within the log.
+    pub index: usize,
+
+    /// The name and number of requested words of the syscall found in the
+    /// log, or `None` if the log has no more entries.
+    pub expected: Option<(String, u32)>,
+
+    /// The name and number of requested words of the syscall issued by the
+    /// guest.
+    pub actual: (String, u32),
+}
+
+impl core::fmt::Display for SyscallDivergence {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let (name, words) = &self.actual;
+        write!(
+            f,
+            "Syscall replay diverged at call {}: guest called {name:?} requesting {words} words, ",
+            self.index
+        )?;
+        match &self.expected {
+            Some((name, words)) => write!(f, "log has {name:?} requesting {words} words"),
+            None => write!(f, "log has no more entries"),
+        }
+    }
+}
+
+impl std::error::Error for SyscallDivergence {}
+
+pub(crate) struct SyscallRecorder<'a, H: Handlers<'a>> {
+    writer: Arc<Mutex<H::Writer>>,
+}
+
+impl<'a, H: Handlers<'a>> SyscallRecorder<'a, H> {
+    pub fn new(writer: Arc<Mutex<H::Writer>>) -> Self {
+        Self { writer }
+    }
+
+    pub fn record(
+        &mut self,
+        name: &str,
+        to_guest_words: u32,
+        record: &SyscallRecord,
+    ) -> Result<()> {
+        let entry = SyscallLogEntry {
+            name: name.to_string(),
+            to_guest_words,
+            record: record.clone(),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        bincode::serialize_into(&mut *writer, &entry)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+pub(crate) struct SyscallReplayer<'a, H: Handlers<'a>> {
+    reader: Arc<Mutex<H::Reader>>,
+    index: usize,
+}
+
+impl<'a, H: Handlers<'a>> SyscallReplayer<'a, H> {
+    pub fn new(reader: Arc<Mutex<H::Reader>>) -> Self {
+        Self { reader, index: 0 }
+    }
+
+    /// Returns the recorded result for the next syscall, which must match the
+    /// given `name` and `to_guest_words`.
+    pub fn next(&mut self, name: &str, to_guest_words: u32) -> Result<SyscallRecord> {
+        let index = self.index;
+        self.index += 1;
+
+        let mut reader = self.reader.lock().unwrap();
+        let entry = match bincode::deserialize_from::<_, SyscallLogEntry>(&mut *reader) {
+            Ok(entry) => Some(entry),
+            Err(err) => match err.as_ref() {
+                bincode::ErrorKind::Io(io_err) if io_err.kind() == ErrorKind::UnexpectedEof => None,
+                _ => return Err(err.into()),
+            },
+        };
+
+        match entry {
+            Some(entry) if entry.name == name && entry.to_guest_words == to_guest_words => {
+                Ok(entry.record)
+            }
+            entry => Err(SyscallDivergence {
+                index,
+                expected: entry.map(|entry| (entry.name, entry.to_guest_words)),
+                actual: (name.to_string(), to_guest_words),
+            }
+            .into()),
+        }
+    }
+}
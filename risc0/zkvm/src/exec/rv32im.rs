@@ -0,0 +1,441 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An interpreter for RV32IM instructions.
+//!
+//! Instructions are decoded once into an [Insn], which the [MemoryMonitor]
+//! caches alongside the pages of memory they were decoded from. Executing an
+//! [Insn] operates directly on the registers held by the [MemoryMonitor].
+
+use risc0_zkvm_platform::{memory::MEM_SIZE, WORD_SIZE};
+use rrs_lib::{
+    instruction_formats::{
+        BType, IType, ITypeShamt, JType, RType, SType, UType, OPCODE_AUIPC, OPCODE_BRANCH,
+        OPCODE_JAL, OPCODE_JALR, OPCODE_LOAD, OPCODE_LUI, OPCODE_OP, OPCODE_OP_IMM, OPCODE_STORE,
+        OPCODE_SYSTEM,
+    },
+    MemAccessSize, Memory,
+};
+
+use super::monitor::MemoryMonitor;
+use crate::opcode::OpCode;
+
+/// A fault raised by the guest, which stops execution.
+#[derive(Clone, Copy, Debug)]
+pub enum Fault {
+    /// The given word is not a valid instruction.
+    InvalidInstruction(u32),
+    /// An access to the given address wasn't aligned to its size.
+    MisalignedAccess(u32),
+    /// An access to the given address was outside of guest memory.
+    OutOfBounds(u32),
+}
+
+/// An instruction along with the metadata used to account for its cycles.
+#[derive(Clone, Copy)]
+pub struct Decoded {
+    pub opcode: OpCode,
+    pub insn: Insn,
+}
+
+impl Decoded {
+    pub fn new(word: u32, pc: u32) -> Result<Self, Fault> {
+        let opcode = OpCode::decode(word, pc).map_err(|_| Fault::InvalidInstruction(word))?;
+        let insn = Insn::decode(word).ok_or(Fault::InvalidInstruction(word))?;
+        Ok(Self { opcode, insn })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+    Mul,
+    Mulh,
+    Mulhsu,
+    Mulhu,
+    Div,
+    Divu,
+    Rem,
+    Remu,
+}
+
+impl AluOp {
+    fn apply(self, a: u32, b: u32) -> u32 {
+        let sign_extend = |x: u32| x as i32 as i64;
+        match self {
+            AluOp::Add => a.wrapping_add(b),
+            AluOp::Sub => a.wrapping_sub(b),
+            AluOp::Sll => a << (b & 0x1f),
+            AluOp::Slt => ((a as i32) < (b as i32)) as u32,
+            AluOp::Sltu => (a < b) as u32,
+            AluOp::Xor => a ^ b,
+            AluOp::Srl => a >> (b & 0x1f),
+            AluOp::Sra => ((a as i32) >> (b & 0x1f)) as u32,
+            AluOp::Or => a | b,
+            AluOp::And => a & b,
+            AluOp::Mul => a.wrapping_mul(b),
+            AluOp::Mulh => (sign_extend(a).wrapping_mul(sign_extend(b)) >> 32) as u32,
+            AluOp::Mulhsu => (sign_extend(a).wrapping_mul(b as i64) >> 32) as u32,
+            AluOp::Mulhu => ((a as u64).wrapping_mul(b as u64) >> 32) as u32,
+            AluOp::Div => match b {
+                0 => u32::MAX,
+                _ => (a as i32).wrapping_div(b as i32) as u32,
+            },
+            AluOp::Divu => match b {
+                0 => u32::MAX,
+                _ => a / b,
+            },
+            AluOp::Rem => match b {
+                0 => a,
+                _ => (a as i32).wrapping_rem(b as i32) as u32,
+            },
+            AluOp::Remu => match b {
+                0 => a,
+                _ => a % b,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum BranchOp {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+impl BranchOp {
+    fn taken(self, a: u32, b: u32) -> bool {
+        match self {
+            BranchOp::Eq => a == b,
+            BranchOp::Ne => a != b,
+            BranchOp::Lt => (a as i32) < (b as i32),
+            BranchOp::Ge => (a as i32) >= (b as i32),
+            BranchOp::Ltu => a < b,
+            BranchOp::Geu => a >= b,
+        }
+    }
+}
+
+/// A decoded RV32IM instruction.
+#[derive(Clone, Copy)]
+pub enum Insn {
+    Lui {
+        rd: usize,
+        imm: u32,
+    },
+    Auipc {
+        rd: usize,
+        imm: u32,
+    },
+    Jal {
+        rd: usize,
+        imm: u32,
+    },
+    Jalr {
+        rd: usize,
+        rs1: usize,
+        imm: u32,
+    },
+    Branch {
+        op: BranchOp,
+        rs1: usize,
+        rs2: usize,
+        imm: u32,
+    },
+    Load {
+        size: MemAccessSize,
+        signed: bool,
+        rd: usize,
+        rs1: usize,
+        imm: u32,
+    },
+    Store {
+        size: MemAccessSize,
+        rs1: usize,
+        rs2: usize,
+        imm: u32,
+    },
+    AluImm {
+        op: AluOp,
+        rd: usize,
+        rs1: usize,
+        imm: u32,
+    },
+    Alu {
+        op: AluOp,
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+    },
+    /// ECALL and EBREAK, which are handled by the executor.
+    System,
+}
+
+impl Insn {
+    /// Decode an instruction, returning `None` if it isn't supported.
+    pub fn decode(word: u32) -> Option<Self> {
+        Some(match word & 0x7f {
+            OPCODE_LUI => {
+                let dec = UType::new(word);
+                Insn::Lui {
+                    rd: dec.rd,
+                    imm: dec.imm as u32,
+                }
+            }
+            OPCODE_AUIPC => {
+                let dec = UType::new(word);
+                Insn::Auipc {
+                    rd: dec.rd,
+                    imm: dec.imm as u32,
+                }
+            }
+            OPCODE_JAL => {
+                let dec = JType::new(word);
+                Insn::Jal {
+                    rd: dec.rd,
+                    imm: dec.imm as u32,
+                }
+            }
+            OPCODE_JALR => {
+                let dec = IType::new(word);
+                Insn::Jalr {
+                    rd: dec.rd,
+                    rs1: dec.rs1,
+                    imm: dec.imm as u32,
+                }
+            }
+            OPCODE_BRANCH => {
+                let dec = BType::new(word);
+                let op = match dec.funct3 {
+                    0b000 => BranchOp::Eq,
+                    0b001 => BranchOp::Ne,
+                    0b100 => BranchOp::Lt,
+                    0b101 => BranchOp::Ge,
+                    0b110 => BranchOp::Ltu,
+                    0b111 => BranchOp::Geu,
+                    _ => return None,
+                };
+                Insn::Branch {
+                    op,
+                    rs1: dec.rs1,
+                    rs2: dec.rs2,
+                    imm: dec.imm as u32,
+                }
+            }
+            OPCODE_LOAD => {
+                let dec = IType::new(word);
+                let (size, signed) = match dec.funct3 {
+                    0b000 => (MemAccessSize::Byte, true),
+                    0b001 => (MemAccessSize::HalfWord, true),
+                    0b010 => (MemAccessSize::Word, false),
+                    0b100 => (MemAccessSize::Byte, false),
+                    0b101 => (MemAccessSize::HalfWord, false),
+                    _ => return None,
+                };
+                Insn::Load {
+                    size,
+                    signed,
+                    rd: dec.rd,
+                    rs1: dec.rs1,
+                    imm: dec.imm as u32,
+                }
+            }
+            OPCODE_STORE => {
+                let dec = SType::new(word);
+                let size = match dec.funct3 {
+                    0b000 => MemAccessSize::Byte,
+                    0b001 => MemAccessSize::HalfWord,
+                    0b010 => MemAccessSize::Word,
+                    _ => return None,
+                };
+                Insn::Store {
+                    size,
+                    rs1: dec.rs1,
+                    rs2: dec.rs2,
+                    imm: dec.imm as u32,
+                }
+            }
+            OPCODE_OP_IMM => {
+                let dec = IType::new(word);
+                let shamt = ITypeShamt::new(word);
+                let (op, imm) = match (dec.funct3, shamt.funct7) {
+                    (0b000, _) => (AluOp::Add, dec.imm as u32),
+                    (0b001, 0b000_0000) => (AluOp::Sll, shamt.shamt),
+                    (0b010, _) => (AluOp::Slt, dec.imm as u32),
+                    (0b011, _) => (AluOp::Sltu, dec.imm as u32),
+                    (0b100, _) => (AluOp::Xor, dec.imm as u32),
+                    (0b101, 0b000_0000) => (AluOp::Srl, shamt.shamt),
+                    (0b101, 0b010_0000) => (AluOp::Sra, shamt.shamt),
+                    (0b110, _) => (AluOp::Or, dec.imm as u32),
+                    (0b111, _) => (AluOp::And, dec.imm as u32),
+                    _ => return None,
+                };
+                Insn::AluImm {
+                    op,
+                    rd: dec.rd,
+                    rs1: dec.rs1,
+                    imm,
+                }
+            }
+            OPCODE_OP => {
+                let dec = RType::new(word);
+                let op = match (dec.funct3, dec.funct7) {
+                    (0b000, 0b000_0000) => AluOp::Add,
+                    (0b000, 0b010_0000) => AluOp::Sub,
+                    (0b001, 0b000_0000) => AluOp::Sll,
+                    (0b010, 0b000_0000) => AluOp::Slt,
+                    (0b011, 0b000_0000) => AluOp::Sltu,
+                    (0b100, 0b000_0000) => AluOp::Xor,
+                    (0b101, 0b000_0000) => AluOp::Srl,
+                    (0b101, 0b010_0000) => AluOp::Sra,
+                    (0b110, 0b000_0000) => AluOp::Or,
+                    (0b111, 0b000_0000) => AluOp::And,
+                    (0b000, 0b000_0001) => AluOp::Mul,
+                    (0b001, 0b000_0001) => AluOp::Mulh,
+                    (0b010, 0b000_0001) => AluOp::Mulhsu,
+                    (0b011, 0b000_0001) => AluOp::Mulhu,
+                    (0b100, 0b000_0001) => AluOp::Div,
+                    (0b101, 0b000_0001) => AluOp::Divu,
+                    (0b110, 0b000_0001) => AluOp::Rem,
+                    (0b111, 0b000_0001) => AluOp::Remu,
+                    _ => return None,
+                };
+                Insn::Alu {
+                    op,
+                    rd: dec.rd,
+                    rs1: dec.rs1,
+                    rs2: dec.rs2,
+                }
+            }
+            OPCODE_SYSTEM => Insn::System,
+            _ => return None,
+        })
+    }
+
+    /// Returns true if this instruction may transfer control somewhere other
+    /// than the next instruction, which ends a basic block.
+    pub fn ends_block(&self) -> bool {
+        matches!(
+            self,
+            Insn::Jal { .. } | Insn::Jalr { .. } | Insn::Branch { .. } | Insn::System
+        )
+    }
+}
+
+/// Execute the instruction at `pc`, returning the address of the next
+/// instruction.
+///
+/// [Insn::System] instructions must be handled by the caller.
+pub fn execute(mem: &mut MemoryMonitor, pc: u32, insn: Insn) -> Result<u32, Fault> {
+    let load = |mem: &MemoryMonitor, idx: usize| match idx {
+        0 => 0,
+        _ => mem.load_register(idx),
+    };
+    let store = |mem: &mut MemoryMonitor, idx: usize, data: u32| {
+        if idx != 0 {
+            mem.store_register(idx, data);
+        }
+    };
+
+    match insn {
+        Insn::Lui { rd, imm } => store(mem, rd, imm),
+        Insn::Auipc { rd, imm } => store(mem, rd, pc.wrapping_add(imm)),
+        Insn::Jal { rd, imm } => {
+            store(mem, rd, pc + WORD_SIZE as u32);
+            return Ok(pc.wrapping_add(imm));
+        }
+        Insn::Jalr { rd, rs1, imm } => {
+            let target = load(mem, rs1).wrapping_add(imm) & !1;
+            store(mem, rd, pc + WORD_SIZE as u32);
+            return Ok(target);
+        }
+        Insn::Branch { op, rs1, rs2, imm } => {
+            if op.taken(load(mem, rs1), load(mem, rs2)) {
+                return Ok(pc.wrapping_add(imm));
+            }
+        }
+        Insn::Load {
+            size,
+            signed,
+            rd,
+            rs1,
+            imm,
+        } => {
+            let addr = load(mem, rs1).wrapping_add(imm);
+            check_access(addr, size)?;
+            let data = mem.read_mem(addr, size).unwrap();
+            let data = match (size, signed) {
+                (MemAccessSize::Byte, true) => data as i8 as u32,
+                (MemAccessSize::HalfWord, true) => data as i16 as u32,
+                _ => data,
+            };
+            store(mem, rd, data);
+        }
+        Insn::Store {
+            size,
+            rs1,
+            rs2,
+            imm,
+        } => {
+            let addr = load(mem, rs1).wrapping_add(imm);
+            let data = load(mem, rs2);
+            check_access(addr, size)?;
+            mem.write_mem(addr, size, data);
+        }
+        Insn::AluImm { op, rd, rs1, imm } => store(mem, rd, op.apply(load(mem, rs1), imm)),
+        Insn::Alu { op, rd, rs1, rs2 } => store(mem, rd, op.apply(load(mem, rs1), load(mem, rs2))),
+        Insn::System => unreachable!("System instructions must be handled by the executor"),
+    }
+    Ok(pc + WORD_SIZE as u32)
+}
+
+/// Check a guest pointer to `len` bytes passed to an ecall, which must be word
+/// aligned like the guest's own word accesses.
+pub fn check_region(addr: u32, len: usize) -> Result<(), Fault> {
+    if addr % WORD_SIZE as u32 != 0 {
+        return Err(Fault::MisalignedAccess(addr));
+    }
+    if addr as usize + len > MEM_SIZE {
+        return Err(Fault::OutOfBounds(addr));
+    }
+    Ok(())
+}
+
+fn check_access(addr: u32, size: MemAccessSize) -> Result<(), Fault> {
+    let len = match size {
+        MemAccessSize::Byte => 1,
+        MemAccessSize::HalfWord => 2,
+        MemAccessSize::Word => 4,
+    };
+    if addr % len != 0 {
+        return Err(Fault::MisalignedAccess(addr));
+    }
+    if addr as usize + len as usize > MEM_SIZE {
+        return Err(Fault::OutOfBounds(addr));
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracing of the syscalls issued by the guest.
+//!
+//! A syscall trace is a stream of JSON lines, one [SyscallTraceEntry] for
+//! each syscall issued by the guest, in the order in which they were issued.
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{env::Handlers, io::SyscallContext};
+
+/// A record of a single syscall made by the guest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyscallTraceEntry {
+    /// The name of the syscall.
+    pub name: String,
+
+    /// The session cycle at which the syscall was issued.
+    pub cycle: usize,
+
+    /// The program counter of the `ecall` instruction.
+    pub pc: u32,
+
+    /// The number of words the guest requested be returned to it.
+    pub to_guest_words: u32,
+
+    /// The values returned to the guest in registers a0 and a1.
+    pub regs: (u32, u32),
+
+    /// The regions of guest memory read by the syscall handler, in the order
+    /// in which they were read.
+    pub reads: Vec<RegionRead>,
+}
+
+/// A region of guest memory read by a syscall handler with
+/// [SyscallContext::load_region].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RegionRead {
+    /// The address of the start of the region.
+    pub addr: u32,
+
+    /// The bytes that were read, encoded as hex in the trace.
+    #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+    pub data: Vec<u8>,
+}
+
+fn to_hex<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(data))
+}
+
+fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    hex::decode(s).map_err(serde::de::Error::custom)
+}
+
+pub(crate) struct SyscallTracer<'a, H: Handlers<'a>> {
+    writer: Arc<Mutex<H::Writer>>,
+}
+
+impl<'a, H: Handlers<'a>> SyscallTracer<'a, H> {
+    pub fn new(writer: Arc<Mutex<H::Writer>>) -> Self {
+        Self { writer }
+    }
+
+    pub fn trace(&mut self, entry: &SyscallTraceEntry) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut *writer, entry)?;
+        // Flush each entry, so that the trace of a guest that hangs is
+        // complete up to the point where it stopped.
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// A [SyscallContext] which records the regions of memory read through it.
+pub(crate) struct TracingContext<'a> {
+    pub inner: &'a mut dyn SyscallContext,
+    pub reads: &'a mut Vec<RegionRead>,
+}
+
+impl<'a> SyscallContext for TracingContext<'a> {
+    fn get_cycle(&self) -> usize {
+        self.inner.get_cycle()
+    }
+
+    fn load_register(&mut self, idx: usize) -> u32 {
+        self.inner.load_register(idx)
+    }
+
+    fn load_region(&mut self, addr: u32, size: u32) -> Vec<u8> {
+        let data = self.inner.load_region(addr, size);
+        self.reads.push(RegionRead {
+            addr,
+            data: data.clone(),
+        });
+        data
+    }
+
+    fn load_u32(&mut self, addr: u32) -> u32 {
+        self.inner.load_u32(addr)
+    }
+
+    fn load_u8(&mut self, addr: u32) -> u8 {
+        self.inner.load_u8(addr)
+    }
+
+    fn load_string(&mut self, addr: u32) -> Result<String> {
+        self.inner.load_string(addr)
+    }
+}
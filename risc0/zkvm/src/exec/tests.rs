@@ -12,20 +12,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::BTreeMap, io::Cursor, str::from_utf8, sync::Mutex};
+use std::{
+    collections::BTreeMap, io::Cursor, path::Path, str::from_utf8, sync::Mutex, time::Duration,
+};
 
 use anyhow::Result;
 use risc0_zkvm_methods::{
     multi_test::{MultiTestSpec, SYS_MULTI_TEST},
     HELLO_COMMIT_ELF, MULTI_TEST_ELF, SLICE_IO_ELF, STANDARD_LIB_ELF,
 };
-use risc0_zkvm_platform::{fileno, PAGE_SIZE, WORD_SIZE};
+use risc0_zkvm_platform::{
+    fileno,
+    memory::MEM_SIZE,
+    syscall::{
+        ecall,
+        nr::{SYS_RANDOM, SYS_READ, SYS_WRITE},
+    },
+    PAGE_SIZE, WORD_SIZE,
+};
 use test_log::test;
 
-use super::{Executor, ExecutorEnv, TraceEvent};
+use super::{
+    diff_traces,
+    io::{syscalls, MAX_OPEN_FILES},
+    CancellationToken, Executor, ExecutorCheckpoint, ExecutorEnv, ExecutorError, StopReason,
+    SyscallDivergence, SyscallTraceEntry, TraceEvent, TraceRecorder, WatchAccess,
+};
 use crate::{
     serde::{from_slice, to_vec},
-    testutils, ExitCode, MemoryImage, Program, Session,
+    testutils, ExitCode, MemoryImage, OutputStream, Program, RandomSource, Session,
 };
 
 #[test]
@@ -55,6 +70,155 @@ fn basic() {
     assert_eq!(segments[0].index, 0);
 }
 
+#[cfg(feature = "gdbstub")]
+#[test]
+fn gdb_stub() {
+    use std::io::{Read, Write};
+
+    struct MockConn {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockConn {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockConn {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let requests = [
+        "?",
+        "Z0,4008,4",
+        "c",
+        "p2",
+        "p20",
+        "s",
+        "p1",
+        "m4000,4",
+        "Mfffffffc,8:0000000000000000",
+        "M4000,2:zz",
+        "Z2,fffffffe,4",
+        "z2,ffffffff,4",
+        "c",
+    ];
+    let input: String = requests
+        .iter()
+        .map(|packet| {
+            let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+            format!("${packet}#{checksum:02x}")
+        })
+        .collect();
+    let mut conn = MockConn {
+        input: Cursor::new(input.into_bytes()),
+        output: Vec::new(),
+    };
+
+    let image = BTreeMap::from([
+        (0x4000, 0x1234b137), // lui x2, 0x1234b000
+        (0x4004, 0xf387e1b7), // lui x3, 0xf387e000
+        (0x4008, 0x003100b3), // add x1, x2, x3
+        (0x400c, 0x00000073), // ecall(halt)
+    ]);
+    let program = Program {
+        entry: 0x4000,
+        image,
+    };
+    let image = MemoryImage::new(&program, PAGE_SIZE as u32).unwrap();
+    let mut exec = Executor::new(ExecutorEnv::default(), image, program.entry);
+    let session = exec.run_with_gdb(&mut conn).unwrap();
+    assert_eq!(session.exit_code, ExitCode::Halted(0));
+
+    let output = from_utf8(&conn.output).unwrap().replace('+', "");
+    let replies: Vec<&str> = output
+        .split('$')
+        .skip(1)
+        .map(|packet| packet.split_once('#').unwrap().0)
+        .collect();
+    assert_eq!(
+        replies,
+        [
+            "S05",      // ?
+            "OK",       // Z0,4008,4
+            "S05",      // c
+            "00b03412", // p2
+            "08400000", // p20
+            "S05",      // s
+            "0090bc05", // p1
+            "37b13412", // m4000,4
+            "E01",      // M out of range
+            "E01",      // M with malformed data
+            "E01",      // Z2 out of range
+            "E01",      // z out of range
+            "W00",      // c
+        ]
+    );
+}
+
+#[test]
+fn breakpoints_and_watchpoints() {
+    let image = BTreeMap::from([
+        (0x4000, 0x1234b137), // lui x2, 0x1234b000
+        (0x4004, 0x000051b7), // lui x3, 0x00005000
+        (0x4008, 0x0021a023), // sw x2, 0(x3)
+        (0x400c, 0x0001a083), // lw x1, 0(x3)
+        (0x4010, 0x00000073), // ecall(halt)
+    ]);
+    let program = Program {
+        entry: 0x4000,
+        image,
+    };
+    let image = MemoryImage::new(&program, PAGE_SIZE as u32).unwrap();
+    let mut exec = Executor::new(ExecutorEnv::default(), image, program.entry);
+    exec.add_breakpoint(0x4008);
+    exec.add_watchpoint(0x5000..0x5004, WatchAccess::ReadWrite);
+
+    assert!(matches!(
+        exec.run_until_stop().unwrap(),
+        StopReason::Breakpoint { pc: 0x4008 }
+    ));
+    assert_eq!(exec.load_register(2), 0x1234b000);
+    assert_eq!(exec.read_memory(0x5000, 4).unwrap(), [0; 4]);
+
+    assert!(matches!(
+        exec.run_until_stop().unwrap(),
+        StopReason::Watchpoint {
+            pc: 0x4008,
+            addr: 0x5000,
+            access: WatchAccess::Write
+        }
+    ));
+    assert_eq!(exec.pc(), 0x400c);
+    assert_eq!(
+        exec.read_memory(0x5000, 4).unwrap(),
+        0x1234b000_u32.to_le_bytes()
+    );
+
+    assert!(matches!(
+        exec.run_until_stop().unwrap(),
+        StopReason::Watchpoint {
+            pc: 0x400c,
+            addr: 0x5000,
+            access: WatchAccess::Read
+        }
+    ));
+    assert_eq!(exec.load_register(1), 0x1234b000);
+
+    match exec.run_until_stop().unwrap() {
+        StopReason::Exited(session) => assert_eq!(session.exit_code, ExitCode::Halted(0)),
+        _ => panic!("expected the guest to exit"),
+    }
+}
+
 #[test]
 fn system_split() {
     let entry = 0x4000;
@@ -95,6 +259,85 @@ fn system_split() {
     assert_eq!(segments[1].index, 1);
 }
 
+#[test]
+fn checkpoint_resume() {
+    let run = |spec: MultiTestSpec, rng_seed: Option<u64>| {
+        let mut builder = ExecutorEnv::builder();
+        builder.add_input(&to_vec(&spec).unwrap());
+        if let Some(seed) = rng_seed {
+            builder.rng_seed(seed);
+        }
+        let mut exec = Executor::from_elf(builder.build().unwrap(), MULTI_TEST_ELF).unwrap();
+        let session = exec.run().unwrap();
+        assert_eq!(session.exit_code, ExitCode::Paused(0));
+        let paused = session.resolve().unwrap();
+        let post_image_id = paused.last().unwrap().post_image_id;
+
+        // Save the checkpoint to disk, as if to be loaded by another process.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+        exec.checkpoint().unwrap().save(&path).unwrap();
+
+        // Resume the original executor, for comparison.
+        let expected = exec.run().unwrap();
+        assert_eq!(expected.exit_code, ExitCode::Halted(0));
+
+        let checkpoint = ExecutorCheckpoint::load(&path).unwrap();
+        let mut exec = Executor::from_checkpoint(ExecutorEnv::default(), checkpoint);
+        let session = exec.run().unwrap();
+        assert_eq!(session.exit_code, ExitCode::Halted(0));
+        assert_eq!(session.journal, expected.journal);
+        let segments = session.resolve().unwrap();
+        let expected = expected.resolve().unwrap();
+        assert_eq!(segments[0].pre_image.compute_id(), post_image_id);
+        assert_eq!(segments.len(), expected.len());
+        for (segment, expected) in segments.iter().zip(expected.iter()) {
+            assert_eq!(
+                segment.pre_image.compute_id(),
+                expected.pre_image.compute_id()
+            );
+            assert_eq!(segment.post_image_id, expected.post_image_id);
+            assert_eq!(segment.exit_code, expected.exit_code);
+            assert_eq!(segment.random, expected.random);
+        }
+        assert!(exec.checkpoint().is_ok());
+    };
+
+    run(MultiTestSpec::PauseContinue, None);
+    // The seeded random numbers continue from where the checkpoint left off.
+    run(MultiTestSpec::DoRandomPause, Some(1));
+}
+
+#[test]
+fn execute_only() {
+    let input = to_vec(&MultiTestSpec::ShaDigest {
+        data: "hello".into(),
+    })
+    .unwrap();
+    let env = ExecutorEnv::builder().add_input(&input).build().unwrap();
+    let session = Executor::from_elf(env, MULTI_TEST_ELF)
+        .unwrap()
+        .run()
+        .unwrap();
+
+    let env = ExecutorEnv::builder().add_input(&input).build().unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let summary = exec.execute_only().unwrap();
+    assert_eq!(summary.exit_code, session.exit_code);
+    assert_eq!(summary.journal, session.journal);
+    assert_eq!(summary.cycles, session.stats.insn_cycles);
+
+    // A paused execution resumes from the state held by the executor.
+    let env = ExecutorEnv::builder()
+        .add_input(&to_vec(&MultiTestSpec::PauseContinue).unwrap())
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    assert_eq!(exec.execute_only().unwrap().exit_code, ExitCode::Paused(0));
+    assert_eq!(exec.execute_only().unwrap().exit_code, ExitCode::Halted(0));
+    assert!(exec.run().is_err());
+}
+
 #[test]
 fn libm_build() {
     let env = ExecutorEnv::builder()
@@ -135,6 +378,37 @@ fn host_syscall() {
     assert_eq!(*actual.lock().unwrap(), expected[..expected.len() - 1]);
 }
 
+#[test]
+fn session_stats() {
+    let input = to_vec(&MultiTestSpec::ShaDigest {
+        data: "hello".into(),
+    })
+    .unwrap();
+    let env = ExecutorEnv::builder().add_input(&input).build().unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let session = exec.run().unwrap();
+    let stats = &session.stats;
+
+    let segments = session.resolve().unwrap();
+    assert_eq!(stats.segments.len(), segments.len());
+    assert_eq!(
+        stats.insn_cycles,
+        segments.iter().map(|segment| segment.insn_cycles).sum()
+    );
+    for (stats, segment) in stats.segments.iter().zip(segments.iter()) {
+        assert_eq!(stats.po2, segment.po2);
+        assert_eq!(stats.cycles + stats.padding_cycles, 1 << segment.po2);
+    }
+
+    assert!(stats.sha_cycles > 0);
+    assert_eq!(stats.bigint_cycles, 0);
+    assert!(stats.page_read_cycles > 0);
+    assert!(stats.pages_read > 0);
+    let write = &stats.syscalls[risc0_zkvm_platform::syscall::nr::SYS_WRITE.as_str()];
+    assert!(write.calls > 0);
+    assert!(write.cycles >= write.calls);
+}
+
 // Make sure panics in the callback get propagated correctly.
 #[test]
 #[should_panic(expected = "I am panicking from here!")]
@@ -151,6 +425,77 @@ fn host_syscall_callback_panic() {
     exec.run().unwrap();
 }
 
+#[test]
+fn syscall_replay() {
+    let input = to_vec(&MultiTestSpec::DoRandom).unwrap();
+    let mut log = Vec::new();
+    let env = ExecutorEnv::builder()
+        .add_input(&input)
+        .record_syscalls(&mut log)
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let recorded = exec.run().unwrap();
+    drop(exec);
+
+    // Both the input and the random bytes come from the log.
+    let env = ExecutorEnv::builder()
+        .replay_syscalls(log.as_slice())
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let replayed = exec.run().unwrap();
+    assert_eq!(replayed.journal, recorded.journal);
+
+    // A different guest diverges from the log on its first syscall.
+    let env = ExecutorEnv::builder()
+        .replay_syscalls(log.as_slice())
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, HELLO_COMMIT_ELF).unwrap();
+    let err = exec.run().err().unwrap();
+    let divergence = err.downcast_ref::<SyscallDivergence>().unwrap();
+    assert_eq!(divergence.index, 0);
+    assert!(divergence.expected.is_some());
+}
+
+#[test]
+fn syscall_trace() {
+    let input = to_vec(&MultiTestSpec::ShaDigest {
+        data: "hello".into(),
+    })
+    .unwrap();
+    let mut trace = Vec::new();
+    let env = ExecutorEnv::builder()
+        .add_input(&input)
+        .trace_syscalls(&mut trace)
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let session = exec.run().unwrap();
+    drop(exec);
+
+    let entries: Vec<SyscallTraceEntry> = from_utf8(&trace)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(entries
+        .windows(2)
+        .all(|pair| pair[0].cycle <= pair[1].cycle));
+
+    assert!(entries.iter().any(|entry| entry.name == SYS_READ.as_str()));
+
+    // The journal is committed through SYS_WRITE, which reads it from guest
+    // memory.
+    let written: Vec<u8> = entries
+        .iter()
+        .filter(|entry| entry.name == SYS_WRITE.as_str())
+        .flat_map(|entry| entry.reads.iter().flat_map(|read| read.data.clone()))
+        .collect();
+    assert_eq!(written, session.journal);
+}
+
 #[test]
 fn sha_accel() {
     let input = to_vec(&MultiTestSpec::ShaConforms).unwrap();
@@ -208,6 +553,44 @@ fn stdio() {
     assert_eq!(MSG, from_utf8(&stdout).unwrap());
 }
 
+#[test]
+fn capture_output() {
+    const MSG: &str = "Hello world!  This is a test of captured output.";
+    const FD: u32 = 123;
+    let spec = to_vec(&MultiTestSpec::CopyToStdout { fd: FD }).unwrap();
+    let mut stdout: Vec<u8> = Vec::new();
+    let session = {
+        let env = ExecutorEnv::builder()
+            .read_fd(FD, MSG.as_bytes())
+            .stdin(bytemuck::cast_slice(&spec))
+            .stdout(&mut stdout)
+            .capture_output(true)
+            .output_level(OutputStream::Stdout, None)
+            .build()
+            .unwrap();
+        let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+        exec.run().unwrap()
+    };
+    assert!(stdout.is_empty());
+
+    // The guest writes in small chunks, each of which is captured separately.
+    assert!(session.output.len() > 1);
+    assert!(session
+        .output
+        .iter()
+        .all(|output| output.stream == OutputStream::Stdout && output.segment == 0));
+    assert!(session
+        .output
+        .windows(2)
+        .all(|pair| pair[0].cycle < pair[1].cycle));
+    let captured: Vec<u8> = session
+        .output
+        .iter()
+        .flat_map(|output| output.data.iter().copied())
+        .collect();
+    assert_eq!(MSG, from_utf8(&captured).unwrap());
+}
+
 // Tests sys_read into a buffer of bytes that may not be word aligned.
 //
 // To make sure we don't miss any edge cases, this tries all permutations of
@@ -290,6 +673,117 @@ fn posix_style_read() {
     }
 }
 
+#[test]
+fn virtual_fs() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let run = |path: &str, offset: u32| -> Option<(u32, Vec<u8>)> {
+        let spec = to_vec(&MultiTestSpec::ReadFile {
+            path: path.to_string(),
+            offset,
+        })
+        .unwrap();
+        let env = ExecutorEnv::builder()
+            .mount("/data/hello.txt", b"hello world".as_slice())
+            .mount("/crate", crate_dir)
+            .add_input(&spec)
+            .build()
+            .unwrap();
+        let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+        let session = exec.run().unwrap();
+        from_slice(&session.journal).unwrap()
+    };
+
+    let hello = b"hello world".to_vec();
+    assert_eq!(run("/data/hello.txt", 0), Some((11, hello)));
+    assert_eq!(run("data/./hello.txt", 6), Some((11, b"world".to_vec())));
+    let manifest = std::fs::read(crate_dir.join("Cargo.toml")).unwrap();
+    assert_eq!(
+        run("/crate/Cargo.toml", 0),
+        Some((manifest.len() as u32, manifest))
+    );
+
+    // Unmounted paths, directories, and paths escaping a mount aren't found.
+    assert_eq!(run("/data/missing.txt", 0), None);
+    assert_eq!(run("/data/hello.txt/more", 0), None);
+    assert_eq!(run("/crate/src", 0), None);
+    assert_eq!(run("/crate/../Cargo.toml", 0), None);
+
+    let err = ExecutorEnv::builder()
+        .mount("/a/../b", b"".as_slice())
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Unable to mount /a/../b: /a/../b must not contain `..`"
+    );
+}
+
+#[test]
+fn virtual_fs_close() {
+    let spec = to_vec(&MultiTestSpec::OpenFiles {
+        path: "/data/hello.txt".to_string(),
+        count: 4 * MAX_OPEN_FILES as u32,
+    })
+    .unwrap();
+    let env = ExecutorEnv::builder()
+        .mount("/data/hello.txt", b"hello world".as_slice())
+        .add_input(&spec)
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let session = exec.run().unwrap();
+    let open: u32 = from_slice(&session.journal).unwrap();
+    assert_eq!(open as usize, MAX_OPEN_FILES);
+}
+
+#[test]
+fn virtual_fs_bad_fd() {
+    let spec = to_vec(&MultiTestSpec::BadFileDescriptor { fd: 42 }).unwrap();
+    let env = ExecutorEnv::builder().add_input(&spec).build().unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let session = exec.run().unwrap();
+    let (pos, size): (u32, u32) = from_slice(&session.journal).unwrap();
+    assert_eq!((pos, size), (u32::MAX, u32::MAX));
+}
+
+#[test]
+fn named_inputs() {
+    let env = ExecutorEnv::builder()
+        .input("greeting", &"hello".to_string())
+        .input("count", &1u32)
+        .input("count", &2u32)
+        .add_input(&to_vec(&MultiTestSpec::NamedInputs).unwrap())
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let session = exec.run().unwrap();
+    let actual: (String, u32) = from_slice(&session.journal).unwrap();
+    assert_eq!(actual, ("hello".to_string(), 2));
+}
+
+#[test]
+fn named_input_serialize_error() {
+    struct Unserializable;
+
+    impl serde::Serialize for Unserializable {
+        fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("unserializable"))
+        }
+    }
+
+    let err = ExecutorEnv::builder()
+        .input("bad", &Unserializable)
+        .input("good", &1u32)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "Unable to serialize input bad: Not supported"
+    );
+}
+
 #[test]
 fn environment() {
     let env = ExecutorEnv::builder()
@@ -324,12 +818,89 @@ fn commit_hello_world() {
     exec.run().unwrap();
 }
 
+#[test]
+fn run_on_thread() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    let input = to_vec(&MultiTestSpec::Syscall { count: 2 }).unwrap();
+    let env = ExecutorEnv::send_builder()
+        .add_input(&input)
+        .io_callback_send(SYS_MULTI_TEST, |buf: &[u8]| -> Vec<u8> { buf.to_vec() })
+        .build()
+        .unwrap();
+    assert_send(&env);
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    assert_send(&exec);
+
+    let session = std::thread::spawn(move || exec.run())
+        .join()
+        .unwrap()
+        .unwrap();
+    assert_eq!(session.exit_code, ExitCode::Halted(0));
+}
+
+#[test]
+fn local_env_is_not_send() {
+    // Only one of these applies to a type which isn't Send, while both apply
+    // to a type which is, so the paths below fail to compile if the types are.
+    trait AmbiguousIfSend<A> {
+        fn some_item() {}
+    }
+    impl<T: ?Sized> AmbiguousIfSend<()> for T {}
+    impl<T: ?Sized + Send> AmbiguousIfSend<u8> for T {}
+
+    let _ = <ExecutorEnv<'static> as AmbiguousIfSend<_>>::some_item;
+    let _ = <Executor<'static> as AmbiguousIfSend<_>>::some_item;
+}
+
 #[test]
 fn random() {
     let spec = to_vec(&MultiTestSpec::DoRandom).unwrap();
     let env = ExecutorEnv::builder().add_input(&spec).build().unwrap();
     let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
-    exec.run().unwrap();
+    let session = exec.run().unwrap();
+    let segments = session.resolve().unwrap();
+    assert_eq!(segments.last().unwrap().random, Some(RandomSource::Os));
+
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .syscall(SYS_RANDOM, syscalls::Random::seeded(1))
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let segments = exec.run().unwrap().resolve().unwrap();
+    assert_eq!(segments.last().unwrap().random, Some(RandomSource::Custom));
+}
+
+#[test]
+fn rng_seed() {
+    let run = |seed: u64| {
+        let spec = to_vec(&MultiTestSpec::DoRandom).unwrap();
+        let env = ExecutorEnv::builder()
+            .add_input(&spec)
+            .rng_seed(seed)
+            .build()
+            .unwrap();
+        let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+        exec.run().unwrap()
+    };
+
+    let session = run(1);
+    assert_eq!(session.journal, run(1).journal);
+    assert_ne!(session.journal, run(2).journal);
+    let segments = session.resolve().unwrap();
+    assert_eq!(segments.last().unwrap().random, Some(RandomSource::Seeded));
+
+    // Segments that didn't use randomness don't record a source.
+    let spec = to_vec(&MultiTestSpec::DoNothing).unwrap();
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .rng_seed(1)
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let segments = exec.run().unwrap().resolve().unwrap();
+    assert!(segments.iter().all(|segment| segment.random.is_none()));
 }
 
 #[test]
@@ -358,6 +929,108 @@ fn fail() {
     let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
     let err = exec.run().err().unwrap();
     assert!(err.to_string().contains("MultiTestSpec::Fail invoked"));
+    match err.downcast::<ExecutorError>().unwrap() {
+        ExecutorError::GuestPanic { message, context } => {
+            assert!(message.contains("MultiTestSpec::Fail invoked"));
+            assert_eq!(context.segment_index, 0);
+            assert!(context.cycle > 0);
+            assert_eq!(context.backtrace, None);
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+}
+
+#[cfg(feature = "backtrace")]
+#[test]
+fn fail_backtrace() {
+    let spec = to_vec(&MultiTestSpec::Fail).unwrap();
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .backtrace(true)
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let err = exec.run().err().unwrap();
+    assert!(err.to_string().contains("guest backtrace:"));
+    let context = err.downcast::<ExecutorError>().unwrap().context().cloned();
+    let backtrace = context.unwrap().backtrace.unwrap();
+    assert!(!backtrace.frames.is_empty());
+    assert!(backtrace.frames.iter().any(|frame| frame
+        .function
+        .as_deref()
+        .unwrap_or_default()
+        .contains("main")));
+}
+
+#[test]
+fn fault_errors() {
+    let run = |image: BTreeMap<u32, u32>| {
+        let program = Program {
+            entry: 0x4000,
+            image,
+        };
+        let image = MemoryImage::new(&program, PAGE_SIZE as u32).unwrap();
+        let mut exec = Executor::new(ExecutorEnv::default(), image, program.entry);
+        exec.run()
+            .err()
+            .unwrap()
+            .downcast::<ExecutorError>()
+            .unwrap()
+    };
+
+    match run(BTreeMap::from([(0x4000, 0x00000000)])) {
+        ExecutorError::InvalidInstruction { insn, context } => {
+            assert_eq!(insn, 0);
+            assert_eq!(context.pc, 0x4000);
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+
+    match run(BTreeMap::from([
+        (0x4000, 0x000040b7), // lui x1, 0x4
+        (0x4004, 0x0010a103), // lw x2, 1(x1)
+    ])) {
+        ExecutorError::MisalignedAccess { addr, context } => {
+            assert_eq!(addr, 0x4001);
+            assert_eq!(context.pc, 0x4004);
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+
+    match run(BTreeMap::from([
+        (0x4000, 0x00300293), // addi t0, x0, 3 (sha)
+        (0x4004, 0xfffff5b7), // lui a1, 0xfffff
+        (0x4008, 0x00000073), // ecall
+    ])) {
+        ExecutorError::OutOfBounds { addr, context } => {
+            assert_eq!(addr, 0xfffff000);
+            assert_eq!(context.pc, 0x4008);
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+
+    match run(BTreeMap::from([
+        (0x4000, 0x00100593), // addi a1, x0, 1
+        (0x4004, 0x00000073), // ecall (halt)
+    ])) {
+        ExecutorError::MisalignedAccess { addr, context } => {
+            assert_eq!(addr, 1);
+            assert_eq!(context.pc, 0x4004);
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+
+    match run(BTreeMap::from([
+        (0x4000, 0x00400293), // addi t0, x0, 4 (bigint)
+        (0x4004, 0x00100593), // addi a1, x0, 1
+        (0x4008, 0x00000073), // ecall
+    ])) {
+        ExecutorError::InvalidEcallArgs { ecall, context, .. } => {
+            assert_eq!(ecall, ecall::BIGINT);
+            assert_eq!(context.pc, 0x4008);
+        }
+        err => panic!("unexpected error: {err}"),
+    }
 }
 
 #[cfg(feature = "profiler")]
@@ -441,6 +1114,143 @@ fn profiler() {
     );
 }
 
+#[cfg(feature = "profiler")]
+#[test]
+fn profiler_folded() {
+    use crate::exec::profiler::Profiler;
+
+    let mut prof = Profiler::new("multi_test.elf", MULTI_TEST_ELF).unwrap();
+    {
+        let env = ExecutorEnv::builder()
+            .add_input(&to_vec(&MultiTestSpec::Profiler).unwrap())
+            .trace_callback(prof.make_trace_callback())
+            .build()
+            .unwrap();
+        let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+        exec.run().unwrap();
+    }
+
+    let mut folded = Vec::new();
+    prof.write_folded(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    // profile_test_func2 is inlined into profile_test_func1, which is called
+    // from main.
+    assert!(
+        folded.lines().any(|line| {
+            let (stack, _count) = line.rsplit_once(' ').unwrap();
+            stack.contains("main;") && stack.ends_with(";profile_test_func1;profile_test_func2")
+        }),
+        "{folded}"
+    );
+
+    let mut svg = Vec::new();
+    prof.write_flamegraph(&mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.contains("<svg"));
+    assert!(svg.contains("profile_test_func1"));
+
+    // Nothing was run, so there's nothing to draw.
+    let prof = Profiler::new("multi_test.elf", MULTI_TEST_ELF).unwrap();
+    assert!(prof.write_flamegraph(Vec::new()).is_err());
+}
+
+#[cfg(feature = "coverage")]
+#[test]
+fn coverage() {
+    use crate::exec::coverage::Coverage;
+
+    let mut coverage = Coverage::new(MULTI_TEST_ELF).unwrap();
+    {
+        let env = ExecutorEnv::builder()
+            .add_input(&to_vec(&MultiTestSpec::Profiler).unwrap())
+            .trace_callback(coverage.make_trace_callback())
+            .build()
+            .unwrap();
+        let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+        exec.run().unwrap();
+    }
+
+    // Only the Profiler arm of the guest ran, so some lines of it must have
+    // been executed and others not.
+    let files = coverage.lines().unwrap();
+    let (_, lines) = files
+        .iter()
+        .find(|(file, _)| file.ends_with("multi_test.rs"))
+        .unwrap();
+    assert!(lines.values().any(|&count| count > 0));
+    assert!(lines.values().any(|&count| count == 0));
+
+    let mut lcov = Vec::new();
+    coverage.write_lcov(&mut lcov).unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    assert!(lcov.starts_with("TN:\n"));
+    assert!(lcov
+        .lines()
+        .any(|line| line.starts_with("SF:") && line.ends_with("multi_test.rs")));
+    assert_eq!(
+        lcov.matches("SF:").count(),
+        lcov.matches("end_of_record").count()
+    );
+}
+
+// Record a trace of the guest writing `value` to memory.
+fn record_trace(value: u32) -> Vec<u8> {
+    let spec = MultiTestSpec::ReadWriteMem {
+        values: vec![(0x0600_0000, value)],
+    };
+    let mut trace = Vec::new();
+    {
+        let mut recorder = TraceRecorder::new(&mut trace);
+        let env = ExecutorEnv::builder()
+            .add_input(&to_vec(&spec).unwrap())
+            .trace_callback(recorder.make_trace_callback())
+            .build()
+            .unwrap();
+        let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+        exec.run().unwrap();
+    }
+    trace
+}
+
+#[test]
+fn trace_diff() {
+    let trace1 = record_trace(1);
+    assert!(!trace1.is_empty());
+    assert!(diff_traces(trace1.as_slice(), record_trace(1).as_slice())
+        .unwrap()
+        .is_none());
+
+    // The input is copied into guest memory by the first syscall, so the runs
+    // diverge at the same instruction.
+    let divergence = diff_traces(trace1.as_slice(), record_trace(2).as_slice())
+        .unwrap()
+        .unwrap();
+    let (left, right) = (divergence.left.unwrap(), divergence.right.unwrap());
+    assert_eq!(left.pc, right.pc);
+    assert_ne!(left.memory, right.memory);
+
+    // A truncated trace diverges where it ends.
+    let divergence = diff_traces(trace1.as_slice(), &[][..]).unwrap().unwrap();
+    assert_eq!(divergence.index, 0);
+    assert!(divergence.right.is_none());
+
+    // A trace which ends part way through a step is an error.
+    assert!(diff_traces(trace1.as_slice(), &trace1[..trace1.len() - 1]).is_err());
+}
+
+#[cfg(feature = "backtrace")]
+#[test]
+fn trace_diff_symbolize() {
+    let mut divergence = diff_traces(record_trace(1).as_slice(), record_trace(2).as_slice())
+        .unwrap()
+        .unwrap();
+    divergence
+        .symbolize(MULTI_TEST_ELF, MULTI_TEST_ELF)
+        .unwrap();
+    assert!(!divergence.left_location.unwrap().frames.is_empty());
+    assert!(!divergence.right_location.unwrap().frames.is_empty());
+}
+
 #[test]
 fn trace() {
     let mut events: Vec<TraceEvent> = Vec::new();
@@ -453,7 +1263,21 @@ fn trace() {
         let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
         exec.run().unwrap();
     }
-    let occurances = events
+    // The other kinds of event are checked separately below.
+    let steps: Vec<_> = events
+        .iter()
+        .filter(|event| {
+            matches!(
+                event,
+                TraceEvent::InstructionStart { .. }
+                    | TraceEvent::RegisterSet { .. }
+                    | TraceEvent::MemorySet { .. }
+            )
+        })
+        .cloned()
+        .collect();
+    let mut li_pc = None;
+    let occurances = steps
         .windows(4)
         .filter_map(|window| {
             if let &[TraceEvent::InstructionStart {
@@ -489,6 +1313,7 @@ fn trace() {
                     "program counter should advance one word: {:#?}",
                     window
                 );
+                li_pc = Some(pc1);
                 Some(())
             } else {
                 None
@@ -496,10 +1321,64 @@ fn trace() {
         })
         .count();
     assert_eq!(occurances, 1, "trace events: {:#?}", &events);
+    let li_pc = li_pc.unwrap();
+    assert!(events.iter().any(|event| matches!(
+        event,
+        TraceEvent::InstructionCycles {
+            pc,
+            insn: 1,
+            accel: 0,
+            syscall: 0,
+            ..
+        } if *pc == li_pc
+    )));
     assert!(events.contains(&TraceEvent::MemorySet {
         addr: 0x08000224,
         value: 1337
     }));
+
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, TraceEvent::MemoryRead { .. })));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, TraceEvent::PageIn { .. })));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, TraceEvent::PageOut { .. })));
+    // The input is read with a syscall.
+    assert!(events.contains(&TraceEvent::EcallStart {
+        ecall: ecall::SOFTWARE,
+        syscall: Some(SYS_READ.as_str().to_string()),
+    }));
+    let ecalls: Vec<_> = events
+        .iter()
+        .filter_map(|event| match event {
+            TraceEvent::EcallStart { ecall, .. } => Some((true, *ecall)),
+            TraceEvent::EcallEnd { ecall } => Some((false, *ecall)),
+            _ => None,
+        })
+        .collect();
+    assert!(ecalls
+        .chunks(2)
+        .all(|pair| matches!(pair, [(true, start), (false, end)] if start == end)));
+    // Memory read by ecalls, such as the name of a syscall, is traced too.
+    let mut in_ecall = false;
+    assert!(events.iter().any(|event| {
+        match event {
+            TraceEvent::EcallStart { .. } => in_ecall = true,
+            TraceEvent::EcallEnd { .. } => in_ecall = false,
+            _ => (),
+        }
+        in_ecall && matches!(event, TraceEvent::MemoryRead { .. })
+    }));
+    assert_eq!(
+        events.last(),
+        Some(&TraceEvent::SegmentEnd {
+            index: 0,
+            exit_code: ExitCode::Halted(0),
+        })
+    );
 }
 
 #[test]
@@ -553,3 +1432,277 @@ fn session_limit() {
 
     assert!(run_session(1 << 16, 15, 10).is_ok());
 }
+
+#[test]
+fn session_limit_partial_session() {
+    let spec = &to_vec(&MultiTestSpec::BusyLoop { cycles: 1 << 16 }).unwrap();
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .segment_limit_po2(16)
+        .session_limit(Some(1 << 16))
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let err = exec.run().err().unwrap();
+    let session = match err.downcast::<ExecutorError>().unwrap() {
+        ExecutorError::SessionLimit(session) => session,
+        err => panic!("unexpected error: {err}"),
+    };
+    assert_eq!(session.exit_code, ExitCode::SessionLimit);
+    let segments = session.resolve().unwrap();
+    let (final_segment, segments) = segments.split_last().unwrap();
+    for segment in segments {
+        assert_eq!(segment.exit_code, ExitCode::SystemSplit);
+    }
+    assert_eq!(final_segment.exit_code, ExitCode::SessionLimit);
+
+    // Running again picks up where the previous session stopped.
+    let session = exec.run().unwrap();
+    assert_eq!(session.exit_code, ExitCode::Halted(0));
+}
+
+#[test]
+fn segment_limit_auto() {
+    let run = |auto: bool| {
+        let spec = &to_vec(&MultiTestSpec::BusyLoop {
+            cycles: (1 << 16) + 2000,
+        })
+        .unwrap();
+        let mut builder = ExecutorEnv::builder();
+        builder.add_input(&spec);
+        if auto {
+            builder.segment_limit_auto(Some(17));
+        } else {
+            builder.segment_limit_po2(17);
+        }
+        let env = builder.build().unwrap();
+        let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+        exec.run().unwrap()
+    };
+
+    let fixed = run(false);
+    let fixed_segments = fixed.resolve().unwrap();
+    assert_eq!(fixed_segments.len(), 1);
+    assert_eq!(fixed.stats.total_cycles(), 1 << 17);
+
+    // The final segment is split so that less of it is padding.
+    let auto = run(true);
+    let segments = auto.resolve().unwrap();
+    assert!(segments.len() > 1);
+    assert!(auto.stats.total_cycles() < fixed.stats.total_cycles());
+    assert_eq!(auto.stats.segments.len(), segments.len());
+    assert_eq!(auto.exit_code, ExitCode::Halted(0));
+
+    let (final_segment, segments) = segments.split_last().unwrap();
+    for (idx, segment) in segments.iter().enumerate() {
+        assert_eq!(segment.index, idx as u32);
+        assert_eq!(segment.exit_code, ExitCode::SystemSplit);
+    }
+    assert_eq!(final_segment.exit_code, ExitCode::Halted(0));
+    assert_eq!(final_segment.post_image_id, fixed_segments[0].post_image_id);
+}
+
+#[test]
+fn segment_limit_auto_capture_output() {
+    let spec = &to_vec(&MultiTestSpec::BusyLoop {
+        cycles: (1 << 16) + 2000,
+    })
+    .unwrap();
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .segment_limit_auto(Some(17))
+        .capture_output(true)
+        .output_level(OutputStream::Log, None)
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let session = exec.run().unwrap();
+    let segments = session.resolve().unwrap();
+    assert!(segments.len() > 1);
+
+    // Output written in the resplit final segment belongs to whichever of
+    // the new segments it was written in.
+    let output: Vec<_> = session
+        .output
+        .iter()
+        .map(|output| (output.segment, from_utf8(&output.data).unwrap().trim_end()))
+        .collect();
+    assert_eq!(
+        output,
+        [
+            (0, "Busy loop starting!"),
+            (segments.last().unwrap().index, "Busy loop complete")
+        ]
+    );
+}
+
+#[test]
+fn cancellation() {
+    let spec = &to_vec(&MultiTestSpec::BusyLoop { cycles: 1 << 16 }).unwrap();
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    let mut cancelled = false;
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .segment_limit_po2(16)
+        .cancellation_token(token.clone())
+        .trace_callback(move |event| {
+            if let TraceEvent::InstructionStart { cycle, .. } = event {
+                if !cancelled && cycle >= 10_000 {
+                    cancelled = true;
+                    cancel.cancel();
+                }
+            }
+            Ok(())
+        })
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let err = exec.run().err().unwrap();
+    assert!(
+        matches!(err.downcast_ref(), Some(ExecutorError::Cancelled)),
+        "{err:?}"
+    );
+
+    // The run picks up where it stopped once the token is reset.
+    token.reset();
+    let session = exec.run().unwrap();
+    assert_eq!(session.exit_code, ExitCode::Halted(0));
+    assert_eq!(session.segments.len(), 2);
+}
+
+#[test]
+fn time_limit() {
+    let spec = &to_vec(&MultiTestSpec::BusyLoop { cycles: 1 << 16 }).unwrap();
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .time_limit(Some(Duration::ZERO))
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let err = exec.run().err().unwrap();
+    assert!(
+        matches!(err.downcast_ref(), Some(ExecutorError::TimeLimit)),
+        "{err:?}"
+    );
+
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .time_limit(Some(Duration::from_secs(3600)))
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    assert_eq!(exec.run().unwrap().exit_code, ExitCode::Halted(0));
+}
+
+#[test]
+fn time_limit_with_watchpoints() {
+    let spec = &to_vec(&MultiTestSpec::BusyLoop { cycles: 1 << 22 }).unwrap();
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .time_limit(Some(Duration::from_millis(50)))
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    // Stop at nearly every load, which the run resumes from internally.
+    exec.add_watchpoint(0..MEM_SIZE as u32, WatchAccess::Read);
+    let err = exec.run().err().unwrap();
+    assert!(
+        matches!(err.downcast_ref(), Some(ExecutorError::TimeLimit)),
+        "{err:?}"
+    );
+}
+
+// Run the same program with the interpreter and with the original rrs-based
+// stepping, and check that they agree on everything that affects proving.
+fn assert_matches_legacy(make_exec: impl Fn() -> Executor<'static>) {
+    let run = |legacy_step: bool| {
+        let mut exec = make_exec();
+        exec.legacy_step = legacy_step;
+        exec.run().unwrap()
+    };
+
+    let session = run(false);
+    let legacy = run(true);
+    assert_eq!(session.exit_code, legacy.exit_code);
+    assert_eq!(session.journal, legacy.journal);
+    assert_eq!(
+        format!("{:?}", session.stats),
+        format!("{:?}", legacy.stats)
+    );
+
+    let segments = session.resolve().unwrap();
+    let legacy = legacy.resolve().unwrap();
+    assert_eq!(segments.len(), legacy.len());
+    for (segment, legacy) in segments.iter().zip(legacy.iter()) {
+        assert_eq!(segment.po2, legacy.po2);
+        assert_eq!(segment.insn_cycles, legacy.insn_cycles);
+        assert_eq!(segment.faults.reads, legacy.faults.reads);
+        assert_eq!(segment.faults.writes, legacy.faults.writes);
+        assert_eq!(segment.split_insn, legacy.split_insn);
+        assert_eq!(segment.exit_code, legacy.exit_code);
+        assert_eq!(
+            segment.pre_image.compute_id(),
+            legacy.pre_image.compute_id()
+        );
+        assert_eq!(segment.post_image_id, legacy.post_image_id);
+    }
+}
+
+#[test]
+fn interpreter_matches_legacy() {
+    let mut rng = rand::thread_rng();
+    let case = testutils::generate_bigint_test_cases(&mut rng, 1).remove(0);
+    let specs = [
+        MultiTestSpec::BusyLoop { cycles: 1 << 16 },
+        MultiTestSpec::ShaDigest {
+            data: "hello".into(),
+        },
+        MultiTestSpec::BigInt {
+            x: case.x,
+            y: case.y,
+            modulus: case.modulus,
+        },
+        MultiTestSpec::LibM,
+        MultiTestSpec::ReadWriteMem {
+            values: vec![(0x0600_0000, 1), (0x0600_0000, 0), (0x0600_0400, 2)],
+        },
+    ];
+    for spec in specs {
+        let input = to_vec(&spec).unwrap();
+        assert_matches_legacy(|| {
+            let env = ExecutorEnv::builder()
+                .add_input(&input)
+                .segment_limit_po2(14)
+                .build()
+                .unwrap();
+            Executor::from_elf(env, MULTI_TEST_ELF).unwrap()
+        });
+    }
+}
+
+#[test]
+fn self_modifying_code() {
+    let image = BTreeMap::from([
+        (0x4000, 0x000040b7), // lui x1, 0x4
+        (0x4004, 0x0100a103), // lw x2, 0x10(x1)
+        (0x4008, 0x0020a623), // sw x2, 0xc(x1)
+        (0x400c, 0x0000006f), // jal x0, 0 (replaced by the store above)
+        (0x4010, 0x00000073), // ecall(halt)
+    ]);
+    let program = Program {
+        entry: 0x4000,
+        image,
+    };
+
+    // The block starting at the entry point is decoded before the store
+    // executes, so this only halts if the store invalidates it.
+    assert_matches_legacy(|| {
+        let env = ExecutorEnv::builder()
+            .session_limit(Some(1 << 16))
+            .build()
+            .unwrap();
+        let image = MemoryImage::new(&program, PAGE_SIZE as u32).unwrap();
+        Executor::new(env, image, program.entry)
+    });
+}
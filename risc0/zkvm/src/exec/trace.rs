@@ -0,0 +1,212 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording and comparing execution traces.
+//!
+//! A trace file is a stream of bincode-encoded [TraceStep]s, one for each
+//! instruction executed by the guest, in the order in which they were
+//! executed.
+
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{Backtrace, TraceEvent};
+
+/// The effects of a single instruction, as recorded in a trace file.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TraceStep {
+    /// The session cycle at which the instruction started.
+    pub cycle: u32,
+
+    /// The program counter of the instruction.
+    pub pc: u32,
+
+    /// The registers written by the instruction, with their new values.
+    pub registers: Vec<(u8, u32)>,
+
+    /// The memory written by the instruction, with the values written.
+    pub memory: Vec<(u32, u32)>,
+}
+
+impl core::fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "cycle: {}, pc: 0x{:08x}", self.cycle, self.pc)?;
+        for (reg, value) in self.registers.iter() {
+            write!(f, ", x{reg} = 0x{value:08x}")?;
+        }
+        for (addr, value) in self.memory.iter() {
+            write!(f, ", [0x{addr:08x}] = 0x{value:08x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Records the execution of a guest to a trace file.
+pub struct TraceRecorder<'a> {
+    writer: BufWriter<Box<dyn Write + Send + 'a>>,
+    step: Option<TraceStep>,
+}
+
+impl<'a> TraceRecorder<'a> {
+    /// Construct a [TraceRecorder] which writes the trace to `writer`.
+    pub fn new(writer: impl Write + Send + 'a) -> Self {
+        Self {
+            writer: BufWriter::new(Box::new(writer)),
+            step: None,
+        }
+    }
+
+    /// Returns a callback to populate this recorder, suitable for passing to
+    /// [super::ExecutorEnvBuilder::trace_callback].
+    pub fn make_trace_callback<'b>(
+        &'b mut self,
+    ) -> impl FnMut(TraceEvent) -> Result<()> + Send + 'b {
+        |event| {
+            match event {
+                TraceEvent::InstructionStart { cycle, pc } => {
+                    self.step = Some(TraceStep {
+                        cycle,
+                        pc,
+                        ..Default::default()
+                    });
+                }
+                TraceEvent::RegisterSet { reg, value } => {
+                    if let Some(step) = self.step.as_mut() {
+                        step.registers.push((reg as u8, value));
+                    }
+                }
+                TraceEvent::MemorySet { addr, value } => {
+                    if let Some(step) = self.step.as_mut() {
+                        step.memory.push((addr, value));
+                    }
+                }
+                // This is the last event of each instruction.
+                TraceEvent::InstructionCycles { .. } => {
+                    if let Some(step) = self.step.take() {
+                        bincode::serialize_into(&mut self.writer, &step)?;
+                    }
+                }
+                // Flush at the end of each segment, so that the trace is
+                // complete once the run has finished.
+                TraceEvent::SegmentEnd { .. } => self.writer.flush()?,
+                _ => (),
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The first point at which two traces differ, as found by [diff_traces].
+#[derive(Debug)]
+pub struct TraceDivergence {
+    /// The index of the first instruction whose effects differ.
+    pub index: usize,
+
+    /// The instruction from the left trace, or `None` if it ended first.
+    pub left: Option<TraceStep>,
+
+    /// The instruction from the right trace, or `None` if it ended first.
+    pub right: Option<TraceStep>,
+
+    /// The source location of the left instruction, if symbolized with
+    /// [TraceDivergence::symbolize].
+    pub left_location: Option<Backtrace>,
+
+    /// The source location of the right instruction, if symbolized with
+    /// [TraceDivergence::symbolize].
+    pub right_location: Option<Backtrace>,
+}
+
+impl TraceDivergence {
+    /// Look up the source locations of the divergent instructions in the
+    /// debug info of the ELFs from which each trace was recorded.
+    #[cfg(feature = "backtrace")]
+    pub fn symbolize(&mut self, left_elf: &[u8], right_elf: &[u8]) -> Result<()> {
+        use super::backtrace::Symbols;
+
+        if let Some(step) = self.left.as_ref() {
+            self.left_location = Some(Symbols::new(left_elf)?.locate(step.pc));
+        }
+        if let Some(step) = self.right.as_ref() {
+            self.right_location = Some(Symbols::new(right_elf)?.locate(step.pc));
+        }
+        Ok(())
+    }
+}
+
+impl core::fmt::Display for TraceDivergence {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Traces diverged at instruction {}", self.index)?;
+        for (side, step, location) in [
+            ("left", &self.left, &self.left_location),
+            ("right", &self.right, &self.right_location),
+        ] {
+            match step {
+                Some(step) => write!(f, "\n{side}: {step}")?,
+                None => write!(f, "\n{side}: trace ended")?,
+            }
+            if let Some(location) = location {
+                write!(f, "{location}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare two trace files written by a [TraceRecorder], returning the first
+/// instruction at which they differ, if any.
+///
+/// Instructions are compared by their program counter and the registers and
+/// memory they write; differences in cycle counts alone are not reported.
+/// Fails if either trace is corrupt, or ends part way through an instruction.
+pub fn diff_traces(left: impl Read, right: impl Read) -> Result<Option<TraceDivergence>> {
+    let mut left_reader = TraceReader(BufReader::new(left));
+    let mut right_reader = TraceReader(BufReader::new(right));
+    let mut index = 0;
+    loop {
+        let (left, right) = (left_reader.next()?, right_reader.next()?);
+        let same = match (&left, &right) {
+            (None, None) => return Ok(None),
+            (Some(l), Some(r)) => {
+                l.pc == r.pc && l.registers == r.registers && l.memory == r.memory
+            }
+            _ => false,
+        };
+        if !same {
+            return Ok(Some(TraceDivergence {
+                index,
+                left,
+                right,
+                left_location: None,
+                right_location: None,
+            }));
+        }
+        index += 1;
+    }
+}
+
+struct TraceReader<R>(R);
+
+impl<R: BufRead> TraceReader<R> {
+    // Returns `None` at the end of the trace, which must fall between steps.
+    fn next(&mut self) -> Result<Option<TraceStep>> {
+        if self.0.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let step = bincode::deserialize_from(&mut self.0).context("Truncated or corrupt trace")?;
+        Ok(Some(step))
+    }
+}
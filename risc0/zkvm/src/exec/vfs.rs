@@ -0,0 +1,123 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A read-only virtual filesystem exposed to the guest.
+//!
+//! Host directories, host files and in-memory buffers are mounted at paths in
+//! the guest's filesystem. Guest paths are always `/` separated, and may not
+//! contain `..`, so that the guest can't reach outside of what is mounted.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Seek},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+/// The contents of a path mounted in the guest's virtual filesystem with
+/// [super::ExecutorEnvBuilder::mount].
+#[derive(Clone, Debug)]
+pub enum MountSource {
+    /// A host directory, whose files are visible below the mount point, or a
+    /// single host file, visible at the mount point.
+    Host(PathBuf),
+
+    /// A file with the given contents, visible at the mount point.
+    Bytes(Arc<[u8]>),
+}
+
+impl From<PathBuf> for MountSource {
+    fn from(path: PathBuf) -> Self {
+        Self::Host(path)
+    }
+}
+
+impl From<&Path> for MountSource {
+    fn from(path: &Path) -> Self {
+        Self::Host(path.to_path_buf())
+    }
+}
+
+impl From<Vec<u8>> for MountSource {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes.into())
+    }
+}
+
+impl From<&[u8]> for MountSource {
+    fn from(bytes: &[u8]) -> Self {
+        Self::Bytes(bytes.into())
+    }
+}
+
+/// A file opened from the virtual filesystem.
+pub(crate) trait VfsFile: BufRead + Seek + Send {}
+
+impl<T: BufRead + Seek + Send> VfsFile for T {}
+
+/// The mount table of the virtual filesystem.
+#[derive(Clone, Default)]
+pub(crate) struct Vfs {
+    // Keyed by the components of the mount point.
+    mounts: BTreeMap<Vec<String>, MountSource>,
+}
+
+impl Vfs {
+    pub fn mount(&mut self, path: &str, source: MountSource) -> Result<()> {
+        self.mounts.insert(components(path)?, source);
+        Ok(())
+    }
+
+    /// Open the file at the given guest path, using the innermost mount
+    /// point which contains it.
+    pub fn open(&self, path: &str) -> Result<Box<dyn VfsFile>> {
+        let components = components(path)?;
+        for depth in (0..=components.len()).rev() {
+            let (mount_point, rest) = components.split_at(depth);
+            let Some(source) = self.mounts.get(mount_point) else {
+                continue;
+            };
+            return match source {
+                MountSource::Host(host_path) => {
+                    let file = File::open(rest.iter().fold(host_path.clone(), |p, c| p.join(c)))?;
+                    if file.metadata()?.is_dir() {
+                        bail!("{path} is a directory");
+                    }
+                    Ok(Box::new(BufReader::new(file)))
+                }
+                MountSource::Bytes(bytes) if rest.is_empty() => {
+                    Ok(Box::new(Cursor::new(bytes.clone())))
+                }
+                MountSource::Bytes(_) => bail!("{path} is not a directory"),
+            };
+        }
+        Err(anyhow!("{path} is not mounted"))
+    }
+}
+
+// Split a guest path into its components, ignoring empty and `.` components.
+fn components(path: &str) -> Result<Vec<String>> {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => bail!("{path} must not contain `..`"),
+            _ => components.push(component.to_string()),
+        }
+    }
+    Ok(components)
+}
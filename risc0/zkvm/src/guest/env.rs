@@ -14,6 +14,7 @@
 
 //! Functions for interacting with the host environment.
 
+use alloc::vec;
 use core::{cell::UnsafeCell, default::Default, mem::MaybeUninit, ptr, ptr::null_mut, slice};
 
 use bytemuck::Pod;
@@ -22,7 +23,7 @@ use risc0_zkvm_platform::{
     fileno, memory, syscall,
     syscall::{
         nr::SYS_LOG, sys_alloc_words, sys_cycle_count, sys_halt, sys_log, sys_pause, sys_read,
-        sys_read_words, sys_write, syscall_0, syscall_2, SyscallName,
+        sys_read_named, sys_read_words, sys_write, syscall_0, syscall_2, SyscallName,
     },
     WORD_SIZE,
 };
@@ -31,7 +32,7 @@ use serde::{de::DeserializeOwned, Serialize};
 use crate::{
     align_up,
     guest::{memory_barrier, sha},
-    serde::{Deserializer, Result as SerdeResult, Serializer, WordRead, WordWrite},
+    serde::{from_slice, Deserializer, Result as SerdeResult, Serializer, WordRead, WordWrite},
     sha::rust_crypto::{Digest as _, Output, Sha256},
 };
 
@@ -116,6 +117,26 @@ pub fn read<T: DeserializeOwned>() -> T {
     stdin().read()
 }
 
+/// Read the input the host provided under the given name, and deserialize it.
+///
+/// On the host, named inputs are added with `ExecutorEnvBuilder::input`. They
+/// don't depend on the order of other reads, and the same input may be read
+/// more than once.
+///
+/// # Panics
+///
+/// Panics if the host didn't provide an input with this name, or if it can't
+/// be deserialized as a `T`.
+pub fn read_named<T: DeserializeOwned>(name: &str) -> T {
+    let nwords = unsafe { sys_read_named(null_mut(), 0, name.as_ptr(), name.len()) };
+    if nwords == usize::MAX {
+        panic!("No input named {name}");
+    }
+    let mut words = vec![0u32; nwords];
+    unsafe { sys_read_named(words.as_mut_ptr(), nwords, name.as_ptr(), name.len()) };
+    from_slice(&words).unwrap()
+}
+
 /// Read a slice from the host.
 pub fn read_slice<T: Pod>(slice: &mut [T]) {
     stdin().read_slice(slice)
@@ -0,0 +1,195 @@
+// Copyright 2023 RISC Zero, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Read-only access to files provided by the host.
+//!
+//! The host mounts directories, files and buffers into the guest's virtual
+//! filesystem using `ExecutorEnvBuilder::mount`, and the guest opens them by
+//! path with [File::open]. For example:
+//! ```ignore
+//! use risc0_zkvm::guest::{env::Read, fs::File};
+//!
+//! let mut file = File::open("/data/input.bin").unwrap();
+//! let mut contents = Vec::new();
+//! file.read_to_end(&mut contents);
+//! // Files can also be deserialized from like the standard input.
+//! let config: Config = File::open("/etc/config").unwrap().read();
+//! ```
+
+use alloc::vec::Vec;
+
+use bytemuck::Pod;
+use risc0_zkvm_platform::syscall::{seek, sys_close, sys_open, sys_read, sys_seek, sys_stat};
+use serde::de::DeserializeOwned;
+
+use crate::guest::env::{FdReader, Read};
+
+/// Errors from the virtual filesystem.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// The path isn't mounted, doesn't name a file, or too many files are
+    /// already open.
+    NotFound,
+
+    /// A seek would move before the start of the file, or past the largest
+    /// position the zkVM can address.
+    InvalidSeek,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "File not found"),
+            Error::InvalidSeek => write!(f, "Invalid seek"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match err {
+            Error::NotFound => std::io::ErrorKind::NotFound,
+            Error::InvalidSeek => std::io::ErrorKind::InvalidInput,
+        };
+        std::io::Error::new(kind, err.to_string())
+    }
+}
+
+/// Positions to seek to within a [File].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SeekFrom {
+    /// The given number of bytes from the start of the file.
+    Start(u32),
+
+    /// The size of the file plus the given number of bytes.
+    End(i32),
+
+    /// The current position plus the given number of bytes.
+    Current(i32),
+}
+
+/// A read-only file in the virtual filesystem.
+pub struct File {
+    fd: u32,
+}
+
+impl File {
+    /// Open the file at the given path.
+    pub fn open(path: &str) -> Result<File, Error> {
+        let fd = unsafe { sys_open(path.as_ptr(), path.len()) };
+        if fd == u32::MAX {
+            return Err(Error::NotFound);
+        }
+        Ok(File { fd })
+    }
+
+    /// Return the size of the file in bytes.
+    pub fn size(&self) -> u32 {
+        unsafe { sys_stat(self.fd) }
+    }
+
+    /// Read up to `buf.len()` bytes from the current position, returning the
+    /// number of bytes read. Returns 0 at the end of the file.
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> usize {
+        unsafe { sys_read(self.fd, buf.as_mut_ptr(), buf.len()) }
+    }
+
+    /// Read the rest of the file, appending it to `buf`, and return the
+    /// number of bytes read.
+    pub fn read_to_end(&mut self, buf: &mut Vec<u8>) -> usize {
+        let pos = self.stream_position();
+        let start = buf.len();
+        buf.resize(start + self.size().saturating_sub(pos) as usize, 0);
+        let mut nread = 0;
+        while start + nread < buf.len() {
+            let n = self.read_bytes(&mut buf[start + nread..]);
+            if n == 0 {
+                break;
+            }
+            nread += n;
+        }
+        buf.truncate(start + nread);
+        nread
+    }
+
+    /// Move the current position, returning the new position.
+    pub fn seek(&mut self, pos: SeekFrom) -> Result<u32, Error> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (
+                i32::try_from(offset).map_err(|_| Error::InvalidSeek)?,
+                seek::SET,
+            ),
+            SeekFrom::End(offset) => (offset, seek::END),
+            SeekFrom::Current(offset) => (offset, seek::CUR),
+        };
+        match unsafe { sys_seek(self.fd, offset, whence) } {
+            u32::MAX => Err(Error::InvalidSeek),
+            pos => Ok(pos),
+        }
+    }
+
+    /// Return the current position.
+    pub fn stream_position(&mut self) -> u32 {
+        unsafe { sys_seek(self.fd, 0, seek::CUR) }
+    }
+}
+
+impl Read for File {
+    fn read<T: DeserializeOwned>(&mut self) -> T {
+        FdReader::new(self.fd).read()
+    }
+
+    fn read_slice<T: Pod>(&mut self, buf: &mut [T]) {
+        FdReader::new(self.fd).read_slice(buf)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe { sys_close(self.fd) }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(self.read_bytes(buf))
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Seek for File {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            std::io::SeekFrom::Start(offset) => {
+                SeekFrom::Start(u32::try_from(offset).map_err(|_| Error::InvalidSeek)?)
+            }
+            std::io::SeekFrom::End(offset) => {
+                SeekFrom::End(i32::try_from(offset).map_err(|_| Error::InvalidSeek)?)
+            }
+            std::io::SeekFrom::Current(offset) => {
+                SeekFrom::Current(i32::try_from(offset).map_err(|_| Error::InvalidSeek)?)
+            }
+        };
+        Ok(File::seek(self, pos)? as u64)
+    }
+}
+
+/// Read the entire contents of the file at the given path.
+pub fn read(path: &str) -> Result<Vec<u8>, Error> {
+    let mut contents = Vec::new();
+    File::open(path)?.read_to_end(&mut contents);
+    Ok(contents)
+}
@@ -59,6 +59,7 @@
 
 mod alloc;
 pub mod env;
+pub mod fs;
 pub mod sha;
 
 use core::{arch::asm, mem, ptr};
@@ -49,15 +49,25 @@ pub use risc0_zkvm_platform::{declare_syscall, memory::MEM_SIZE, PAGE_SIZE};
 
 #[cfg(feature = "binfmt")]
 pub use self::binfmt::{elf::Program, image::MemoryImage};
+#[cfg(feature = "coverage")]
+pub use self::exec::coverage::Coverage;
 #[cfg(feature = "profiler")]
 pub use self::exec::profiler::Profiler;
 pub use self::receipt::{ExitCode, SegmentReceipt, SessionFlatReceipt, SessionReceipt};
 #[cfg(feature = "prove")]
 pub use self::{
     exec::io::{Syscall, SyscallContext},
-    exec::{Executor, ExecutorEnv, ExecutorEnvBuilder},
+    exec::{
+        diff_traces, Backtrace, BacktraceFrame, CancellationToken, ExecutionSummary, Executor,
+        ExecutorCheckpoint, ExecutorEnv, ExecutorEnvBuilder, ExecutorError, FaultContext, Handlers,
+        LocalHandlers, MountSource, RegionRead, SendHandlers, StopReason, SyscallDivergence,
+        SyscallTraceEntry, TraceDivergence, TraceEvent, TraceRecorder, TraceStep, WatchAccess,
+    },
     prove::loader::Loader,
-    session::{FileSegmentRef, Segment, SegmentRef, Session, SimpleSegmentRef},
+    session::{
+        FileSegmentRef, GuestOutput, OutputStream, RandomSource, Segment, SegmentRef, SegmentStats,
+        Session, SessionStats, SimpleSegmentRef, SyscallStats,
+    },
 };
 use crate::control_id::{RawControlId, BLAKE2B_CONTROL_ID, POSEIDON_CONTROL_ID, SHA256_CONTROL_ID};
 #[cfg(not(target_os = "zkvm"))]
@@ -17,7 +17,7 @@ use num_traits::FromPrimitive;
 use rrs_lib::{instruction_string_outputter::InstructionStringOutputter, process_instruction};
 
 #[allow(dead_code)]
-#[derive(Debug, num_derive::FromPrimitive, PartialEq)]
+#[derive(Clone, Copy, Debug, num_derive::FromPrimitive, PartialEq)]
 #[repr(u32)]
 pub enum MajorType {
     Compute0,
@@ -36,6 +36,7 @@ pub enum MajorType {
     MuxSize,
 }
 
+#[derive(Clone, Copy)]
 pub struct OpCode {
     pub insn: u32,
     pub insn_pc: u32,
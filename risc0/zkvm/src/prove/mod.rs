@@ -39,9 +39,19 @@ mod plonk;
 #[cfg(test)]
 mod tests;
 
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    cell::{Cell, Ref, RefCell},
+    collections::HashMap,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver},
+        Arc, Condvar, Mutex, Weak,
+    },
+    thread,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use risc0_circuit_rv32im::{
     layout::{OutBuffer, LAYOUT},
     CircuitImpl, REGISTER_GROUP_ACCUM, REGISTER_GROUP_CODE, REGISTER_GROUP_DATA,
@@ -52,18 +62,22 @@ use risc0_core::field::{
 };
 use risc0_zkp::{
     adapter::{CircuitInfo, TapsProvider},
-    core::{digest::DIGEST_WORDS, hash::HashSuite},
+    core::{
+        digest::{Digest, DIGEST_WORDS},
+        hash::HashSuite,
+    },
     hal::{EvalCheck, Hal},
     layout::Buffer,
     prove::{adapter::ProveAdapter, executor::Executor},
     verify::CpuVerifyHal,
 };
 use risc0_zkvm_platform::WORD_SIZE;
+use serde::{Deserialize, Serialize};
 
 use self::{exec::MachineContext, loader::Loader};
 use crate::{
-    receipt::SessionReceipt, ControlId, Segment, SegmentReceipt, Session, SessionFlatReceipt,
-    CIRCUIT,
+    receipt::SessionReceipt, ControlId, Executor as SessionExecutor, Handlers, Segment,
+    SegmentReceipt, SegmentRef, Session, SessionFlatReceipt, CIRCUIT,
 };
 
 /// HAL creation functions for CUDA.
@@ -187,6 +201,96 @@ pub trait Prover {
 
     /// TODO
     fn get_name(&self) -> String;
+
+    /// Verify a [SessionFlatReceipt] assembled from segments proven by this
+    /// prover, using the hash function that this prover uses.
+    fn verify_session(&self, receipt: &SessionFlatReceipt, image_id: Digest) -> Result<()> {
+        Ok(receipt.verify(image_id)?)
+    }
+}
+
+/// Options for proving several segments of a [Session] at the same time.
+#[derive(Clone, Debug)]
+pub struct ParallelOpts {
+    /// The maximum number of segments to prove at the same time.
+    pub max_segments: usize,
+
+    /// The number of bytes that the segments being proven at the same time
+    /// may use together, or `None` for no limit.
+    ///
+    /// The memory used by a segment is estimated from its `po2`, see
+    /// [ParallelOpts::estimate_segment_memory]. A segment may always start
+    /// when no other segment is being proven.
+    pub memory_budget: Option<usize>,
+
+    /// The number of bytes that proving a segment is expected to use for each
+    /// of its cycles, which defaults to [DEFAULT_BYTES_PER_CYCLE].
+    ///
+    /// This depends on the HAL, so it is best measured by proving a segment
+    /// and dividing [Prover::get_peak_memory_usage] by its number of cycles.
+    pub bytes_per_cycle: usize,
+}
+
+/// The default for [ParallelOpts::bytes_per_cycle], as measured with the CPU
+/// HAL, with which a segment of 2^20 cycles fits in about 8GB.
+pub const DEFAULT_BYTES_PER_CYCLE: usize = 8 << 10;
+
+impl Default for ParallelOpts {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl ParallelOpts {
+    /// Construct options for proving up to `max_segments` segments at the same
+    /// time, with no memory budget.
+    pub fn new(max_segments: usize) -> Self {
+        Self {
+            max_segments,
+            memory_budget: None,
+            bytes_per_cycle: DEFAULT_BYTES_PER_CYCLE,
+        }
+    }
+
+    /// Limit the number of bytes that the segments being proven at the same
+    /// time may use together.
+    pub fn with_memory_budget(mut self, memory_budget: usize) -> Self {
+        self.memory_budget = Some(memory_budget);
+        self
+    }
+
+    /// Set the number of bytes that proving a segment is expected to use for
+    /// each of its cycles.
+    pub fn with_bytes_per_cycle(mut self, bytes_per_cycle: usize) -> Self {
+        self.bytes_per_cycle = bytes_per_cycle;
+        self
+    }
+
+    /// Estimate the number of bytes needed to prove a segment of `2^po2`
+    /// cycles.
+    ///
+    /// The memory used by the prover grows linearly with the number of
+    /// cycles, at [ParallelOpts::bytes_per_cycle].
+    pub fn estimate_segment_memory(&self, po2: usize) -> usize {
+        self.bytes_per_cycle.saturating_mul(1 << po2)
+    }
+
+    /// Read the options from the `RISC0_PROVER_PARALLELISM`,
+    /// `RISC0_PROVER_MEMORY_BUDGET` and `RISC0_PROVER_BYTES_PER_CYCLE`
+    /// environment variables, falling back to the [Default] for any that are
+    /// unset.
+    pub fn from_env() -> Self {
+        fn parse_var(name: &str) -> Option<usize> {
+            std::env::var(name).ok()?.parse().ok()
+        }
+        let default = Self::default();
+        Self {
+            max_segments: parse_var("RISC0_PROVER_PARALLELISM").unwrap_or(default.max_segments),
+            memory_budget: parse_var("RISC0_PROVER_MEMORY_BUDGET").or(default.memory_budget),
+            bytes_per_cycle: parse_var("RISC0_PROVER_BYTES_PER_CYCLE")
+                .unwrap_or(default.bytes_per_cycle),
+        }
+    }
 }
 
 /// An implementation of a [Prover] that runs locally.
@@ -197,7 +301,13 @@ where
     E: EvalCheck<H>,
 {
     name: String,
-    hal_eval: HalEval<H, E>,
+    // Created on first use by a prover constructed with
+    // [LocalProver::new_parallel], as it may only prove in parallel.
+    hal_eval: RefCell<Option<HalEval<H, E>>>,
+    hal_eval_fn: Option<fn() -> HalEval<H, E>>,
+    parallel: ParallelOpts,
+    // The peak memory usage reported by the HALs of the parallel workers.
+    worker_memory_usage: Cell<usize>,
 }
 
 impl<H, E> LocalProver<H, E>
@@ -210,8 +320,40 @@ where
     pub fn new(name: &str, hal_eval: HalEval<H, E>) -> Self {
         Self {
             name: name.to_string(),
-            hal_eval,
+            hal_eval: RefCell::new(Some(hal_eval)),
+            hal_eval_fn: None,
+            parallel: ParallelOpts::default(),
+            worker_memory_usage: Cell::new(0),
+        }
+    }
+
+    /// Construct a [LocalProver] with the given name, which proves the
+    /// segments of a [Session] concurrently as configured by `opts`.
+    ///
+    /// Each segment proven concurrently gets its own [HalEval], created on the
+    /// proving thread by calling `hal_eval_fn`. Segments proven on the calling
+    /// thread share a [HalEval], which is created when first needed.
+    pub fn new_parallel(
+        name: &str,
+        hal_eval_fn: fn() -> HalEval<H, E>,
+        opts: ParallelOpts,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            hal_eval: RefCell::new(None),
+            hal_eval_fn: Some(hal_eval_fn),
+            parallel: opts,
+            worker_memory_usage: Cell::new(0),
+        }
+    }
+
+    fn hal_eval(&self) -> Ref<HalEval<H, E>> {
+        if let Some(hal_eval_fn) = self.hal_eval_fn {
+            self.hal_eval.borrow_mut().get_or_insert_with(hal_eval_fn);
         }
+        Ref::map(self.hal_eval.borrow(), |hal_eval| {
+            hal_eval.as_ref().unwrap()
+        })
     }
 }
 
@@ -226,135 +368,298 @@ where
     }
 
     fn get_peak_memory_usage(&self) -> usize {
-        self.hal_eval.hal.get_memory_usage()
+        let usage = self
+            .hal_eval
+            .borrow()
+            .as_ref()
+            .map_or(0, |hal_eval| hal_eval.hal.get_memory_usage());
+        usage.max(self.worker_memory_usage.get())
     }
 
     fn prove_session(&self, session: &Session) -> Result<Box<dyn SessionReceipt>> {
         log::info!("prove_session: {}", self.name);
-        let mut segments = Vec::new();
-        for segment_ref in session.segments.iter() {
-            let segment = segment_ref.resolve()?;
-            segments.push(self.prove_segment(&segment)?);
-        }
+        let segments = match self.hal_eval_fn {
+            Some(hal_eval_fn) if self.parallel.max_segments > 1 => {
+                let (segments, memory_usage) =
+                    prove_segments_parallel(hal_eval_fn, &self.parallel, session)?;
+                self.worker_memory_usage
+                    .set(self.worker_memory_usage.get().max(memory_usage));
+                segments
+            }
+            _ => {
+                let mut segments = Vec::new();
+                for segment_ref in session.segments.iter() {
+                    let segment = segment_ref.resolve()?;
+                    segments.push(self.prove_segment(&segment)?);
+                }
+                segments
+            }
+        };
         let receipt = SessionFlatReceipt {
             segments,
             journal: session.journal.clone(),
         };
         let image_id = session.segments[0].resolve()?.pre_image.compute_id();
+        self.verify_session(&receipt, image_id)?;
+        Ok(Box::new(receipt))
+    }
+
+    fn verify_session(&self, receipt: &SessionFlatReceipt, image_id: Digest) -> Result<()> {
         let hal = CpuVerifyHal::<_, H::HashSuite, _>::new(&crate::CIRCUIT);
         receipt.verify_with_hal(&hal, image_id)?;
-        Ok(Box::new(receipt))
+        Ok(())
     }
 
     fn prove_segment(&self, segment: &Segment) -> Result<SegmentReceipt> {
-        log::info!(
-            "prove_segment[{}]: po2: {}, insn_cycles: {}",
-            segment.index,
-            segment.po2,
-            segment.insn_cycles,
-        );
-        let (hal, eval) = (self.hal_eval.hal.as_ref(), &self.hal_eval.eval);
-
-        let io = segment.prepare_globals();
-        let machine = MachineContext::new(segment);
-        let mut executor = Executor::new(&CIRCUIT, machine, segment.po2, segment.po2, &io);
-
-        let loader = Loader::new();
-        loader.load(|chunk, fini| executor.step(chunk, fini))?;
-        executor.finalize();
-
-        let mut adapter = ProveAdapter::new(&mut executor);
-        let mut prover = risc0_zkp::prove::Prover::new(hal, CIRCUIT.get_taps());
-
-        adapter.execute(prover.iop());
-
-        prover.set_po2(adapter.po2() as usize);
-
-        prover.commit_group(
-            REGISTER_GROUP_CODE,
-            hal.copy_from_elem("code", &adapter.get_code().as_slice()),
-        );
-        prover.commit_group(
-            REGISTER_GROUP_DATA,
-            hal.copy_from_elem("data", &adapter.get_data().as_slice()),
-        );
-        adapter.accumulate(prover.iop());
-        prover.commit_group(
-            REGISTER_GROUP_ACCUM,
-            hal.copy_from_elem("accum", &adapter.get_accum().as_slice()),
-        );
-
-        let mix = hal.copy_from_elem("mix", &adapter.get_mix().as_slice());
-        let out_slice = &adapter.get_io().as_slice();
-
-        log::debug!("Globals: {:?}", OutBuffer(out_slice).tree(&LAYOUT));
-        let out = hal.copy_from_elem("out", &adapter.get_io().as_slice());
-
-        let seal = prover.finalize(&[&mix, &out], eval.as_ref());
-
-        let receipt = SegmentReceipt {
-            seal,
-            index: segment.index,
-        };
-        let hal = CpuVerifyHal::<_, H::HashSuite, _>::new(&crate::CIRCUIT);
-        receipt.verify_with_hal(&hal)?;
+        let hal_eval = self.hal_eval();
+        prove_segment_with_hal(hal_eval.hal.as_ref(), hal_eval.eval.as_ref(), segment)
+    }
+}
+
+fn prove_segment_with_hal<H, E>(hal: &H, eval: &E, segment: &Segment) -> Result<SegmentReceipt>
+where
+    H: Hal<Field = BabyBear, Elem = Elem, ExtElem = ExtElem>,
+    <<H as Hal>::HashSuite as HashSuite<BabyBear>>::HashFn: ControlId,
+    E: EvalCheck<H>,
+{
+    log::info!(
+        "prove_segment[{}]: po2: {}, insn_cycles: {}",
+        segment.index,
+        segment.po2,
+        segment.insn_cycles,
+    );
+
+    let io = segment.prepare_globals();
+    let machine = MachineContext::new(segment);
+    let mut executor = Executor::new(&CIRCUIT, machine, segment.po2, segment.po2, &io);
+
+    let loader = Loader::new();
+    loader.load(|chunk, fini| executor.step(chunk, fini))?;
+    executor.finalize();
+
+    let mut adapter = ProveAdapter::new(&mut executor);
+    let mut prover = risc0_zkp::prove::Prover::new(hal, CIRCUIT.get_taps());
+
+    adapter.execute(prover.iop());
+
+    prover.set_po2(adapter.po2() as usize);
+
+    prover.commit_group(
+        REGISTER_GROUP_CODE,
+        hal.copy_from_elem("code", &adapter.get_code().as_slice()),
+    );
+    prover.commit_group(
+        REGISTER_GROUP_DATA,
+        hal.copy_from_elem("data", &adapter.get_data().as_slice()),
+    );
+    adapter.accumulate(prover.iop());
+    prover.commit_group(
+        REGISTER_GROUP_ACCUM,
+        hal.copy_from_elem("accum", &adapter.get_accum().as_slice()),
+    );
+
+    let mix = hal.copy_from_elem("mix", &adapter.get_mix().as_slice());
+    let out_slice = &adapter.get_io().as_slice();
+
+    log::debug!("Globals: {:?}", OutBuffer(out_slice).tree(&LAYOUT));
+    let out = hal.copy_from_elem("out", &adapter.get_io().as_slice());
+
+    let seal = prover.finalize(&[&mix, &out], eval);
+
+    let receipt = SegmentReceipt {
+        seal,
+        index: segment.index,
+    };
+    let hal = CpuVerifyHal::<_, H::HashSuite, _>::new(&crate::CIRCUIT);
+    receipt.verify_with_hal(&hal)?;
+
+    Ok(receipt)
+}
+
+#[derive(Default)]
+struct ParallelState {
+    running: usize,
+    memory_in_use: usize,
+}
+
+// Prove the segments of `session` on scoped threads, each with its own
+// `HalEval`, while staying within the limits set by `opts`. Returns the
+// receipts along with the peak memory usage reported by those HALs.
+fn prove_segments_parallel<H, E>(
+    hal_eval_fn: fn() -> HalEval<H, E>,
+    opts: &ParallelOpts,
+    session: &Session,
+) -> Result<(Vec<SegmentReceipt>, usize)>
+where
+    H: Hal<Field = BabyBear, Elem = Elem, ExtElem = ExtElem>,
+    <<H as Hal>::HashSuite as HashSuite<BabyBear>>::HashFn: ControlId,
+    E: EvalCheck<H>,
+{
+    let state = &Mutex::new(ParallelState::default());
+    let finished = &Condvar::new();
+    let failed = &AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        let mut receipts = Vec::new();
+        let mut handles = Vec::new();
+        for segment_ref in session.segments.iter() {
+            if failed.load(Ordering::Relaxed) {
+                break;
+            }
+            let segment = segment_ref.resolve()?;
+            let memory = opts.estimate_segment_memory(segment.po2);
+            {
+                let mut state = finished
+                    .wait_while(state.lock().unwrap(), |state| {
+                        state.running > 0
+                            && (state.running >= opts.max_segments
+                                || opts
+                                    .memory_budget
+                                    .map_or(false, |budget| state.memory_in_use + memory > budget))
+                    })
+                    .unwrap();
+                state.running += 1;
+                state.memory_in_use += memory;
+            }
+
+            let handle = scope.spawn(move || {
+                let hal_eval = hal_eval_fn();
+                let result =
+                    prove_segment_with_hal(hal_eval.hal.as_ref(), hal_eval.eval.as_ref(), &segment)
+                        .map(|receipt| (receipt, hal_eval.hal.get_memory_usage()));
+                if result.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                let mut state = state.lock().unwrap();
+                state.running -= 1;
+                state.memory_in_use -= memory;
+                finished.notify_all();
+                result
+            });
+            handles.push(handle);
+        }
+
+        let mut memory_usage = 0;
+        let mut error = None;
+        for handle in handles {
+            match join_prover(handle) {
+                Ok((receipt, usage)) => {
+                    receipts.push(receipt);
+                    memory_usage = memory_usage.max(usage);
+                }
+                Err(err) => {
+                    error.get_or_insert(err);
+                }
+            }
+        }
+        match error {
+            Some(err) => Err(err),
+            None => Ok((receipts, memory_usage)),
+        }
+    })
+}
 
-        Ok(receipt)
+fn join_prover<T>(handle: thread::ScopedJoinHandle<Result<T>>) -> Result<T> {
+    match handle.join() {
+        Ok(result) => result,
+        Err(panic) => std::panic::resume_unwind(panic),
     }
 }
 
-fn provers() -> HashMap<String, Rc<dyn Prover>> {
-    let mut table: HashMap<String, Rc<dyn Prover>> = HashMap::new();
+// Construct the prover with the given name, e.g. "cpu" but not "$default",
+// without constructing any of the others.
+fn new_prover(name: &str) -> Option<Rc<dyn Prover>> {
+    let prover: Rc<dyn Prover> = match name {
+        "cpu" => Rc::new(LocalProver::new_parallel(
+            "cpu",
+            cpu::sha256_hal_eval,
+            ParallelOpts::from_env(),
+        )),
+        "cpu:poseidon" => Rc::new(LocalProver::new_parallel(
+            "cpu:poseidon",
+            cpu::poseidon_hal_eval,
+            ParallelOpts::from_env(),
+        )),
+        #[cfg(feature = "cuda")]
+        "cuda" => Rc::new(LocalProver::new("cuda", cuda::sha256_hal_eval())),
+        #[cfg(feature = "cuda")]
+        "cuda:poseidon" => Rc::new(LocalProver::new("cuda:poseidon", cuda::poseidon_hal_eval())),
+        #[cfg(feature = "metal")]
+        "metal" => Rc::new(LocalProver::new("metal", metal::sha256_hal_eval())),
+        #[cfg(feature = "metal")]
+        "metal:poseidon" => Rc::new(LocalProver::new(
+            "metal:poseidon",
+            metal::poseidon_hal_eval(),
+        )),
+        _ => return None,
+    };
+    Some(prover)
+}
+
+// Every registered prover name, including aliases such as "$default", mapped
+// to the name of the prover that it refers to, as passed to [new_prover].
+fn prover_names() -> HashMap<&'static str, &'static str> {
+    let mut table = HashMap::new();
     {
-        let prover = Rc::new(LocalProver::new("cpu", cpu::sha256_hal_eval()));
-        table.insert("cpu".to_string(), prover.clone());
-        table.insert("$default".to_string(), prover);
+        table.insert("cpu", "cpu");
+        table.insert("$default", "cpu");
 
-        let prover = Rc::new(LocalProver::new("cpu:poseidon", cpu::poseidon_hal_eval()));
-        table.insert("cpu:poseidon".to_string(), prover.clone());
-        table.insert("$poseidon".to_string(), prover);
+        table.insert("cpu:poseidon", "cpu:poseidon");
+        table.insert("$poseidon", "cpu:poseidon");
     }
     #[cfg(feature = "cuda")]
     {
-        let prover = Rc::new(LocalProver::new("cuda", cuda::sha256_hal_eval()));
-        table.insert("cuda".to_string(), prover.clone());
-        table.insert("$gpu".to_string(), prover.clone());
-        table.insert("$default".to_string(), prover);
+        table.insert("cuda", "cuda");
+        table.insert("$gpu", "cuda");
+        table.insert("$default", "cuda");
 
-        let prover = Rc::new(LocalProver::new("cuda:poseidon", cuda::poseidon_hal_eval()));
-        table.insert("cuda:poseidon".to_string(), prover.clone());
-        table.insert("$poseidon".to_string(), prover);
+        table.insert("cuda:poseidon", "cuda:poseidon");
+        table.insert("$poseidon", "cuda:poseidon");
     }
     #[cfg(feature = "metal")]
     {
-        let prover = Rc::new(LocalProver::new("metal", metal::sha256_hal_eval()));
-        table.insert("metal".to_string(), prover.clone());
-        table.insert("$gpu".to_string(), prover.clone());
-        table.insert("$default".to_string(), prover);
+        table.insert("metal", "metal");
+        table.insert("$gpu", "metal");
+        table.insert("$default", "metal");
 
-        let prover = Rc::new(LocalProver::new(
-            "metal:poseidon",
-            metal::poseidon_hal_eval(),
-        ));
-        table.insert("metal:poseidon".to_string(), prover.clone());
-        table.insert("$poseidon".to_string(), prover);
+        table.insert("metal:poseidon", "metal:poseidon");
+        table.insert("$poseidon", "metal:poseidon");
     }
     table
 }
 
+fn provers() -> HashMap<String, Rc<dyn Prover>> {
+    let mut constructed: HashMap<&str, Rc<dyn Prover>> = HashMap::new();
+    prover_names()
+        .into_iter()
+        .map(|(alias, name)| {
+            let prover = constructed
+                .entry(name)
+                .or_insert_with(|| new_prover(name).unwrap())
+                .clone();
+            (alias.to_string(), prover)
+        })
+        .collect()
+}
+
+// Returns the name of the default prover, based on environment variables.
+fn default_prover_name(names: &HashMap<&'static str, &'static str>) -> &'static str {
+    if let Ok(requested) = std::env::var("RISC0_PROVER") {
+        if let Some((name, _)) = names.get_key_value(requested.as_str()) {
+            return *name;
+        }
+    }
+    "$default"
+}
+
 /// Return a default [Prover] based on environment variables, falling back to a
 /// default CPU-based prover.
 pub fn default_prover() -> Rc<dyn Prover> {
     let provers = provers();
-    if let Ok(requested) = std::env::var("RISC0_PROVER") {
-        if let Some(prover) = provers.get(&requested) {
-            return prover.clone();
-        }
-    }
-    if let Some(prover) = provers.get("$default") {
-        return prover.clone();
-    }
-    provers.get("cpu").unwrap().clone()
+    provers
+        .get(default_prover_name(&prover_names()))
+        .unwrap()
+        .clone()
 }
 
 /// Return a [Prover] registered by with specified `name`.
@@ -362,6 +667,201 @@ pub fn get_prover(name: &str) -> Rc<dyn Prover> {
     provers().get(name).unwrap().clone()
 }
 
+/// Return the default [Prover] as chosen by [default_prover], in a form that
+/// can be sent to and shared between threads.
+pub fn default_send_prover() -> Arc<dyn Prover + Send + Sync> {
+    let names = prover_names();
+    Arc::new(SendProver::new(names[default_prover_name(&names)]))
+}
+
+/// Return a [Prover] registered with the specified `name`, in a form that can
+/// be sent to and shared between threads.
+pub fn get_send_prover(name: &str) -> Arc<dyn Prover + Send + Sync> {
+    Arc::new(SendProver::new(prover_names()[name]))
+}
+
+/// A [Prover] which is [Send] and [Sync].
+///
+/// HALs hold state that must stay on the thread that created it, so rather
+/// than holding a HAL, this constructs the named prover on each thread which
+/// uses it, and keeps it for later requests on that thread. A thread drops
+/// the provers it kept for a [SendProver] once that [SendProver] has been
+/// dropped, the next time the thread uses any [SendProver], or when it exits.
+struct SendProver {
+    name: &'static str,
+    peak_memory_usage: AtomicUsize,
+    // Only ever upgraded by the cache, to tell whether this is still alive.
+    alive: Arc<()>,
+}
+
+impl SendProver {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            peak_memory_usage: AtomicUsize::new(0),
+            alive: Arc::new(()),
+        }
+    }
+
+    fn with_prover<T>(&self, f: impl FnOnce(&dyn Prover) -> Result<T>) -> Result<T> {
+        thread_local! {
+            static PROVERS: RefCell<Vec<(Weak<()>, Rc<dyn Prover>)>> = RefCell::new(Vec::new());
+        }
+        let prover = PROVERS.with(|provers| -> Result<_> {
+            let mut provers = provers.borrow_mut();
+            provers.retain(|(owner, _)| owner.strong_count() > 0);
+            let owner = Arc::downgrade(&self.alive);
+            if let Some((_, prover)) = provers.iter().find(|(x, _)| x.ptr_eq(&owner)) {
+                return Ok(prover.clone());
+            }
+            let prover =
+                new_prover(self.name).ok_or_else(|| anyhow!("Unknown prover: {}", self.name))?;
+            provers.push((owner, prover.clone()));
+            Ok(prover)
+        })?;
+        let result = f(prover.as_ref());
+        self.peak_memory_usage
+            .fetch_max(prover.get_peak_memory_usage(), Ordering::Relaxed);
+        result
+    }
+}
+
+impl Prover for SendProver {
+    fn get_name(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn get_peak_memory_usage(&self) -> usize {
+        self.peak_memory_usage.load(Ordering::Relaxed)
+    }
+
+    fn prove_session(&self, session: &Session) -> Result<Box<dyn SessionReceipt>> {
+        self.with_prover(|prover| prover.prove_session(session))
+    }
+
+    fn prove_segment(&self, segment: &Segment) -> Result<SegmentReceipt> {
+        self.with_prover(|prover| prover.prove_segment(segment))
+    }
+
+    fn verify_session(&self, receipt: &SessionFlatReceipt, image_id: Digest) -> Result<()> {
+        self.with_prover(|prover| prover.verify_session(receipt, image_id))
+    }
+}
+
+impl<'a, T: Handlers<'a>> SessionExecutor<'a, T> {
+    /// Run the executor and prove each [Segment] as soon as it is produced,
+    /// using up to `workers` threads.
+    ///
+    /// Segments are handed to the workers through a queue of bounded size, so
+    /// execution waits for the provers when it gets too far ahead. The
+    /// receipts are assembled in segment order and verified before being
+    /// returned.
+    pub fn run_and_prove(
+        &mut self,
+        prover: Arc<dyn Prover + Send + Sync>,
+        workers: usize,
+    ) -> Result<Box<dyn SessionReceipt>> {
+        let workers = workers.max(1);
+        let (sender, receiver) = sync_channel::<Segment>(workers);
+        // Owned only by the workers, so that the receiver is dropped when the
+        // last of them stops, which fails any send blocked on a full queue.
+        let receiver = Arc::new(Mutex::new(receiver));
+        let failed = AtomicBool::new(false);
+        let mut image_id = None;
+
+        let (journal, mut receipts) = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    let receiver = receiver.clone();
+                    let (prover, failed) = (prover.as_ref(), &failed);
+                    scope.spawn(move || prove_segments(prover, &receiver, failed))
+                })
+                .collect();
+            drop(receiver);
+
+            let session = self.run_with_callback(|segment| {
+                if segment.index == 0 {
+                    image_id = Some(segment.pre_image.compute_id());
+                }
+                let index = segment.index;
+                if failed.load(Ordering::Relaxed) || sender.send(segment).is_err() {
+                    bail!("Execution stopped because a segment failed to prove");
+                }
+                Ok(Box::new(ProvedSegmentRef { index }))
+            });
+            if session.is_err() {
+                failed.store(true, Ordering::Relaxed);
+            }
+            drop(sender);
+
+            let mut receipts = Vec::new();
+            let mut error = None;
+            for handle in handles {
+                match handle.join() {
+                    Ok(Ok(worker_receipts)) => receipts.extend(worker_receipts),
+                    Ok(Err(err)) => {
+                        error.get_or_insert(err);
+                    }
+                    Err(panic) => std::panic::resume_unwind(panic),
+                }
+            }
+            // Prefer reporting why proving failed over why execution stopped.
+            match error {
+                Some(err) => Err(err),
+                None => Ok((session?.journal, receipts)),
+            }
+        })?;
+
+        receipts.sort_by_key(|receipt| receipt.index);
+        let receipt = SessionFlatReceipt {
+            segments: receipts,
+            journal,
+        };
+        let image_id = image_id.ok_or(anyhow!("Session has no segments"))?;
+        prover.verify_session(&receipt, image_id)?;
+        Ok(Box::new(receipt))
+    }
+}
+
+/// A [SegmentRef] for a segment which was handed to a prover by
+/// [SessionExecutor::run_and_prove], rather than being kept.
+#[derive(Serialize, Deserialize)]
+struct ProvedSegmentRef {
+    index: u32,
+}
+
+#[typetag::serde]
+impl SegmentRef for ProvedSegmentRef {
+    fn resolve(&self) -> Result<Segment> {
+        bail!("Segment {} was not kept after being proven", self.index)
+    }
+}
+
+// Prove segments from `receiver` until it is closed or any worker has failed.
+fn prove_segments(
+    prover: &(dyn Prover + Send + Sync),
+    receiver: &Mutex<Receiver<Segment>>,
+    failed: &AtomicBool,
+) -> Result<Vec<SegmentReceipt>> {
+    let mut receipts = Vec::new();
+    loop {
+        let segment = match receiver.lock().unwrap().recv() {
+            Ok(segment) => segment,
+            Err(_) => return Ok(receipts),
+        };
+        if failed.load(Ordering::Relaxed) {
+            return Ok(receipts);
+        }
+        match prover.prove_segment(&segment) {
+            Ok(receipt) => receipts.push(receipt),
+            Err(err) => {
+                failed.store(true, Ordering::Relaxed);
+                return Err(err);
+            }
+        }
+    }
+}
+
 impl Session {
     /// For each segment, call [Segment::prove] and collect the receipts.
     pub fn prove(&self) -> Result<Box<dyn SessionReceipt>> {
@@ -12,9 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::rc::Rc;
+use std::{rc::Rc, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use risc0_circuit_rv32im::cpu::CpuEvalCheck;
 use risc0_core::field::baby_bear::BabyBear;
 use risc0_zkp::{
@@ -27,12 +27,13 @@ use risc0_zkvm_platform::{memory, WORD_SIZE};
 use serial_test::serial;
 use test_log::test;
 
-use super::{get_prover, LocalProver, Prover};
+use super::{cpu, default_send_prover, get_prover, LocalProver, ParallelOpts, Prover};
 use crate::{
     prove::HalEval,
     receipt::SessionReceipt,
     serde::{from_slice, to_vec},
-    testutils, Executor, ExecutorEnv, ExitCode, SessionFlatReceipt, CIRCUIT,
+    testutils, Executor, ExecutorEnv, ExitCode, Segment, SegmentReceipt, Session,
+    SessionFlatReceipt, CIRCUIT,
 };
 
 fn prove_nothing(name: &str) -> Result<Box<dyn SessionReceipt>> {
@@ -94,6 +95,27 @@ fn check_image_id() {
     );
 }
 
+#[test]
+#[cfg_attr(feature = "cuda", serial)]
+fn prove_on_thread() {
+    let input = to_vec(&MultiTestSpec::DoNothing).unwrap();
+    let env = ExecutorEnv::send_builder()
+        .add_input(&input)
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let prover = default_send_prover();
+    std::thread::spawn(move || -> Result<()> {
+        let session = exec.run()?;
+        let receipt = prover.prove_session(&session)?;
+        receipt.verify(MULTI_TEST_ID.into())?;
+        Ok(())
+    })
+    .join()
+    .unwrap()
+    .unwrap();
+}
+
 #[test]
 #[serial]
 fn sha_basics() {
@@ -259,6 +281,100 @@ fn continuation() {
     }
 }
 
+#[test]
+#[cfg_attr(feature = "cuda", serial)]
+fn run_and_prove() {
+    let segment_limit_po2 = 16; // 64k cycles
+    let cycles = 1 << segment_limit_po2;
+
+    let spec = &to_vec(&MultiTestSpec::BusyLoop { cycles }).unwrap();
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .segment_limit_po2(segment_limit_po2)
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let receipt = exec.run_and_prove(default_send_prover(), 2).unwrap();
+    receipt.verify(MULTI_TEST_ID.into()).unwrap();
+
+    let receipts = receipt
+        .as_any()
+        .downcast_ref::<SessionFlatReceipt>()
+        .unwrap();
+    assert_eq!(receipts.segments.len(), 2);
+    for (idx, receipt) in receipts.segments.iter().enumerate() {
+        assert_eq!(receipt.index, idx as u32);
+    }
+}
+
+// A prover which fails to prove anything.
+struct FailingProver;
+
+impl Prover for FailingProver {
+    fn get_name(&self) -> String {
+        "failing".to_string()
+    }
+
+    fn get_peak_memory_usage(&self) -> usize {
+        0
+    }
+
+    fn prove_session(&self, _session: &Session) -> Result<Box<dyn SessionReceipt>> {
+        bail!("Unable to prove session")
+    }
+
+    fn prove_segment(&self, _segment: &Segment) -> Result<SegmentReceipt> {
+        bail!("Unable to prove segment")
+    }
+}
+
+#[test]
+fn run_and_prove_failure() {
+    let segment_limit_po2 = 14; // 16k cycles
+
+    // Many more segments than fit in the queue, so that execution would block
+    // if the queue weren't closed when the provers stop.
+    let spec = &to_vec(&MultiTestSpec::BusyLoop { cycles: 1 << 18 }).unwrap();
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .segment_limit_po2(segment_limit_po2)
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let err = exec.run_and_prove(Arc::new(FailingProver), 2).unwrap_err();
+    assert_eq!(err.to_string(), "Unable to prove segment");
+}
+
+#[test]
+fn parallel_prove() {
+    let segment_limit_po2 = 16; // 64k cycles
+    let cycles = 1 << segment_limit_po2;
+
+    let spec = &to_vec(&MultiTestSpec::BusyLoop { cycles }).unwrap();
+    let env = ExecutorEnv::builder()
+        .add_input(&spec)
+        .segment_limit_po2(segment_limit_po2)
+        .build()
+        .unwrap();
+    let mut exec = Executor::from_elf(env, MULTI_TEST_ELF).unwrap();
+    let session = exec.run().unwrap();
+
+    let opts = ParallelOpts::new(2).with_memory_budget(usize::MAX);
+    let prover = LocalProver::new_parallel("cpu", cpu::sha256_hal_eval, opts);
+    let receipt = prover.prove_session(&session).unwrap();
+    receipt.verify(MULTI_TEST_ID.into()).unwrap();
+    assert!(prover.get_peak_memory_usage() > 0);
+
+    let receipts = receipt
+        .as_any()
+        .downcast_ref::<SessionFlatReceipt>()
+        .unwrap();
+    assert_eq!(receipts.segments.len(), 2);
+    for (idx, receipt) in receipts.segments.iter().enumerate() {
+        assert_eq!(receipt.index, idx as u32);
+    }
+}
+
 // These tests come from:
 // https://github.com/riscv-software-src/riscv-tests
 // They were built using the toolchain from:
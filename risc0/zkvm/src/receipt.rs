@@ -99,7 +99,7 @@ use crate::{
 };
 
 /// Indicates how a Segment or Session's execution has terminated
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum ExitCode {
     /// This indicates when a system-initiated split has occured due to the
     /// segment limit being exceeded.
@@ -15,8 +15,9 @@
 //! This module defines [Session] and [Segment] which provides a way to share
 //! execution traces between the execution phase and the proving phase.
 
-use alloc::collections::BTreeSet;
+use alloc::collections::{BTreeMap, BTreeSet};
 use std::{
+    fmt,
     fs::File,
     io::{Read, Write},
     path::{Path, PathBuf},
@@ -52,6 +53,175 @@ pub struct Session {
 
     /// The [ExitCode] of the session.
     pub exit_code: ExitCode,
+
+    /// Statistics about where the cycles of the session were spent.
+    #[serde(default)]
+    pub stats: SessionStats,
+
+    /// The output of the guest, in the order it was written, if captured with
+    /// [crate::ExecutorEnvBuilder::capture_output].
+    #[serde(default)]
+    pub output: Vec<GuestOutput>,
+}
+
+/// A single write of output by the guest, as captured in [Session::output].
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GuestOutput {
+    /// The stream written to.
+    pub stream: OutputStream,
+
+    /// The session cycle at which the guest wrote the output.
+    pub cycle: usize,
+
+    /// The index of the [Segment] in which the guest wrote the output.
+    pub segment: u32,
+
+    /// The bytes written by the guest.
+    pub data: Vec<u8>,
+}
+
+impl fmt::Display for GuestOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data = String::from_utf8_lossy(&self.data);
+        write!(
+            f,
+            "[segment {}, cycle {}] {}: {}",
+            self.segment,
+            self.cycle,
+            self.stream,
+            data.trim_end_matches('\n')
+        )
+    }
+}
+
+/// The streams of guest output which can be captured.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum OutputStream {
+    /// Messages sent with `SYS_LOG`, e.g. by `env::log`.
+    Log,
+
+    /// The guest's standard output.
+    Stdout,
+
+    /// The guest's standard error.
+    Stderr,
+}
+
+impl fmt::Display for OutputStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputStream::Log => write!(f, "log"),
+            OutputStream::Stdout => write!(f, "stdout"),
+            OutputStream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// Cycle counts and other statistics gathered while executing a [Session].
+///
+/// These reflect the cost of proving the session; in particular, the proving
+/// time of each [Segment] is proportional to `1 << po2` cycles.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SessionStats {
+    /// The number of cycles used to execute instructions, including the
+    /// cycles spent in accelerators and syscalls.
+    pub insn_cycles: usize,
+
+    /// The number of cycles spent loading pages of memory.
+    pub page_read_cycles: usize,
+
+    /// The number of cycles spent writing back dirty pages of memory.
+    pub page_write_cycles: usize,
+
+    /// The number of cycles spent in the SHA-256 accelerator.
+    pub sha_cycles: usize,
+
+    /// The number of cycles spent in the BIGINT accelerator.
+    pub bigint_cycles: usize,
+
+    /// Call and cycle counts for each syscall, by name.
+    pub syscalls: BTreeMap<String, SyscallStats>,
+
+    /// Statistics for each [Segment] of the session, in order.
+    pub segments: Vec<SegmentStats>,
+
+    /// The number of distinct pages read by the guest.
+    pub pages_read: usize,
+
+    /// The number of distinct pages written by the guest.
+    pub pages_written: usize,
+}
+
+/// Statistics for a single syscall, as part of [SessionStats].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SyscallStats {
+    /// The number of times the syscall was called.
+    pub calls: usize,
+
+    /// The number of cycles spent in the syscall.
+    pub cycles: usize,
+}
+
+/// Statistics for a single [Segment], as part of [SessionStats].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SegmentStats {
+    /// The number of cycles in powers of 2.
+    pub po2: usize,
+
+    /// The number of cycles used by the segment, including the fixed costs of
+    /// setting up and finishing the segment.
+    pub cycles: usize,
+
+    /// The number of cycles wasted padding the segment to `1 << po2`.
+    pub padding_cycles: usize,
+}
+
+impl SessionStats {
+    /// The total number of cycles that will be proven, including padding.
+    pub fn total_cycles(&self) -> usize {
+        self.segments.iter().map(|segment| 1 << segment.po2).sum()
+    }
+
+    /// The total number of cycles wasted padding segments.
+    pub fn padding_cycles(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|segment| segment.padding_cycles)
+            .sum()
+    }
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "segments: {}", self.segments.len())?;
+        writeln!(f, "total cycles: {}", self.total_cycles())?;
+        writeln!(f, "padding cycles: {}", self.padding_cycles())?;
+        writeln!(f, "instruction cycles: {}", self.insn_cycles)?;
+        writeln!(f, "page read cycles: {}", self.page_read_cycles)?;
+        writeln!(f, "page write cycles: {}", self.page_write_cycles)?;
+        writeln!(f, "sha cycles: {}", self.sha_cycles)?;
+        writeln!(f, "bigint cycles: {}", self.bigint_cycles)?;
+        writeln!(
+            f,
+            "pages read: {}, pages written: {}",
+            self.pages_read, self.pages_written
+        )?;
+        for (name, syscall) in self.syscalls.iter() {
+            writeln!(
+                f,
+                "syscall {name}: calls: {}, cycles: {}",
+                syscall.calls, syscall.cycles
+            )?;
+        }
+        for (idx, segment) in self.segments.iter().enumerate() {
+            writeln!(
+                f,
+                "segment[{idx}]: po2: {}, cycles: {}, padding cycles: {}",
+                segment.po2, segment.cycles, segment.padding_cycles
+            )?;
+        }
+        Ok(())
+    }
 }
 
 /// A reference to a [Segment].
@@ -90,6 +260,28 @@ pub struct Segment {
 
     /// The number of cycles used to execute instructions.
     pub insn_cycles: usize,
+
+    /// The source of the randomness consumed by the guest with `SYS_RANDOM`
+    /// in this segment, or `None` if it didn't consume any.
+    pub random: Option<RandomSource>,
+}
+
+/// Where the random numbers returned to the guest came from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum RandomSource {
+    /// The entropy source of the host operating system.
+    Os,
+
+    /// A CSPRNG seeded with [crate::ExecutorEnvBuilder::rng_seed], so that
+    /// they are predictable.
+    Seeded,
+
+    /// A syscall log given to [crate::ExecutorEnvBuilder::replay_syscalls].
+    Replayed,
+
+    /// A handler for `SYS_RANDOM` added to the [crate::ExecutorEnv], e.g. with
+    /// [crate::ExecutorEnvBuilder::syscall].
+    Custom,
 }
 
 impl Session {
@@ -99,6 +291,8 @@ impl Session {
             segments,
             journal,
             exit_code,
+            stats: SessionStats::default(),
+            output: Vec::new(),
         }
     }
 
@@ -124,6 +318,7 @@ impl Segment {
         po2: usize,
         index: u32,
         insn_cycles: usize,
+        random: Option<RandomSource>,
     ) -> Self {
         log::info!("segment[{index}]> reads: {}, writes: {}, exit_code: {exit_code:?}, split_insn: {split_insn:?}, po2: {po2}, insn_cycles: {insn_cycles}",
             faults.reads.len(),
@@ -139,6 +334,7 @@ impl Segment {
             po2,
             index,
             insn_cycles,
+            random,
         }
     }
 }